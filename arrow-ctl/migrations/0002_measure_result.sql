@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS measure_result (
+	id SERIAL PRIMARY KEY,
+	measure_id INTEGER UNIQUE NOT NULL REFERENCES measure(id) ON UPDATE CASCADE ON DELETE CASCADE,
+	arrow_speed DOUBLE PRECISION NOT NULL,
+	potential_energy DOUBLE PRECISION NOT NULL,
+	kinetic_energy DOUBLE PRECISION NOT NULL,
+	efficiency DOUBLE PRECISION NOT NULL,
+	wasted_energy DOUBLE PRECISION NOT NULL
+);
+DROP TRIGGER IF EXISTS on_insert_measure_result ON measure_result;
+CREATE TRIGGER on_insert_measure_result AFTER INSERT ON measure_result REFERENCING NEW TABLE AS new_table EXECUTE PROCEDURE update_trigger();
+DROP TRIGGER IF EXISTS on_update_measure_result ON measure_result;
+CREATE TRIGGER on_update_measure_result AFTER UPDATE ON measure_result REFERENCING NEW TABLE AS new_table EXECUTE PROCEDURE update_trigger();
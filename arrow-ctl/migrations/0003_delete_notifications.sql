@@ -0,0 +1,26 @@
+-- Idempotent, databases created by the former init script already have the removal triggers.
+
+CREATE OR REPLACE FUNCTION delete_trigger() RETURNS trigger AS $$
+DECLARE
+BEGIN
+  PERFORM pg_notify('delete', TG_TABLE_NAME || ',' || (select string_agg(id::text, ',' ORDER BY id) from old_table));
+  RETURN old;
+END;
+$$ LANGUAGE plpgsql;
+
+-- series are removed together with their bow
+ALTER TABLE measure_series DROP CONSTRAINT IF EXISTS measure_series_bow_id_fkey;
+ALTER TABLE measure_series ADD CONSTRAINT measure_series_bow_id_fkey
+	FOREIGN KEY (bow_id) REFERENCES bow(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+DROP TRIGGER IF EXISTS on_delete_bow ON bow;
+CREATE TRIGGER on_delete_bow AFTER DELETE ON bow REFERENCING OLD TABLE AS old_table EXECUTE PROCEDURE delete_trigger();
+DROP TRIGGER IF EXISTS on_delete_measure_series ON measure_series;
+CREATE TRIGGER on_delete_measure_series AFTER DELETE ON measure_series REFERENCING OLD TABLE AS old_table EXECUTE PROCEDURE delete_trigger();
+DROP TRIGGER IF EXISTS on_delete_arrow ON arrow;
+CREATE TRIGGER on_delete_arrow AFTER DELETE ON arrow REFERENCING OLD TABLE AS old_table EXECUTE PROCEDURE delete_trigger();
+DROP TRIGGER IF EXISTS on_delete_measure ON measure;
+CREATE TRIGGER on_delete_measure AFTER DELETE ON measure REFERENCING OLD TABLE AS old_table EXECUTE PROCEDURE delete_trigger();
+-- measure points are removed together with their measure, no separate notification
+DROP TRIGGER IF EXISTS on_delete_measure_result ON measure_result;
+CREATE TRIGGER on_delete_measure_result AFTER DELETE ON measure_result REFERENCING OLD TABLE AS old_table EXECUTE PROCEDURE delete_trigger();
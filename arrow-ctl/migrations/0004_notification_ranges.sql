@@ -0,0 +1,38 @@
+-- Notification payloads are limited to 8000 bytes, so the ids are sent as ranges
+-- ("1-500,502"), and as "*" if even those do not fit, asking the listener to resync.
+CREATE OR REPLACE FUNCTION notify_ids(channel TEXT, tab TEXT, ids INT[]) RETURNS void AS $$
+DECLARE
+  ranges TEXT;
+BEGIN
+  IF ids IS NULL THEN
+    RETURN;
+  END IF;
+  SELECT string_agg(CASE WHEN first = last THEN first::text ELSE first || '-' || last END, ',' ORDER BY first)
+    INTO ranges
+    FROM (
+      SELECT min(id) AS first, max(id) AS last
+      FROM (SELECT id, id - row_number() OVER (ORDER BY id) AS island FROM unnest(ids) AS id) numbered
+      GROUP BY island
+    ) islands;
+  IF octet_length(tab) + octet_length(ranges) >= 7900 THEN
+    ranges := '*';
+  END IF;
+  PERFORM pg_notify(channel, tab || ',' || ranges);
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION update_trigger() RETURNS trigger AS $$
+DECLARE
+BEGIN
+  PERFORM notify_ids('update', TG_TABLE_NAME, (select array_agg(id) from new_table));
+  RETURN new;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION delete_trigger() RETURNS trigger AS $$
+DECLARE
+BEGIN
+  PERFORM notify_ids('delete', TG_TABLE_NAME, (select array_agg(id) from old_table));
+  RETURN old;
+END;
+$$ LANGUAGE plpgsql;
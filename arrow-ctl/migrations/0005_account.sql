@@ -0,0 +1,8 @@
+-- User accounts for the control interface, no change notifications are sent for them.
+
+CREATE TABLE IF NOT EXISTS account (
+	id SERIAL PRIMARY KEY,
+	name CHARACTER VARYING(256) UNIQUE NOT NULL,
+	password_hash TEXT NOT NULL,
+	role CHARACTER VARYING(16) NOT NULL CHECK (role IN ('viewer', 'operator'))
+);
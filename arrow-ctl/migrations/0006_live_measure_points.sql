@@ -0,0 +1,3 @@
+-- Measure points are streamed live by the controller, notifying their inserts
+-- made the clients receive every point twice.
+DROP TRIGGER IF EXISTS on_insert_measure_point ON measure_point;
@@ -0,0 +1,291 @@
+use super::models::*;
+use super::server::database::ArrowDB;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Format version written to archives, raised on incompatible changes.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("unsupported archive version {0}, expected {}.", ARCHIVE_VERSION)]
+    Version(u32),
+
+    #[error("invalid archive: {0}.")]
+    Invalid(String),
+
+    #[error(transparent)]
+    Sql(#[from] sqlx::Error),
+}
+
+/// A bow with everything measured with it, used to move data between
+/// machines. Ids are those of the exporting database and get remapped on
+/// import.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BowArchive {
+    pub version: u32,
+    pub bow: Bow,
+    pub arrows: Vec<Arrow>,
+    pub series: Vec<SeriesArchive>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SeriesArchive {
+    pub series: MeasureSeries,
+    pub measures: Vec<MeasureArchive>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MeasureArchive {
+    pub measure: Measure,
+    pub points: Vec<MeasurePoint>,
+    #[serde(default)]
+    pub result: Option<MeasureResult>,
+}
+
+pub async fn export_bow<D: ArrowDB>(db: &D, id: i32) -> Result<BowArchive, sqlx::Error> {
+    let bow = db.get_bow(id).await?;
+    let arrows = db.list_arrows(id).await?;
+    let mut series = Vec::new();
+    for s in db.list_measurement_series(id).await? {
+        let mut results = db.list_measure_results(s.id).await?;
+        let mut measures = Vec::new();
+        for measure in db.list_measures(s.id).await? {
+            let points = db.list_measure_points(measure.id).await?;
+            let result = results
+                .iter()
+                .position(|r| r.measure_id == measure.id)
+                .map(|i| results.swap_remove(i));
+            measures.push(MeasureArchive {
+                measure,
+                points,
+                result,
+            });
+        }
+        series.push(SeriesArchive {
+            series: s,
+            measures,
+        });
+    }
+    Ok(BowArchive {
+        version: ARCHIVE_VERSION,
+        bow,
+        arrows,
+        series,
+    })
+}
+
+/// Stores the archived bow as new bow. Names already in use get a numbered
+/// suffix. If the import fails, the partially imported bow is removed again.
+pub async fn import_bow<D: ArrowDB>(db: &D, archive: BowArchive) -> Result<Bow, ArchiveError> {
+    if archive.version != ARCHIVE_VERSION {
+        return Err(ArchiveError::Version(archive.version));
+    }
+    let bows = db.list_bows().await?;
+    let mut series_names = HashSet::new();
+    for bow in &bows {
+        for series in db.list_measurement_series(bow.id).await? {
+            series_names.insert(series.name);
+        }
+    }
+    let bow_names: HashSet<String> = bows.into_iter().map(|b| b.name).collect();
+    let bow = db
+        .add_bow(Bow {
+            name: unique_name(&archive.bow.name, &bow_names),
+            ..archive.bow.clone()
+        })
+        .await?;
+
+    match import_contents(db, &bow, archive, &mut series_names).await {
+        Ok(()) => {
+            info!(target: "arrow::archive", "imported bow '{}' as {}", bow.name, bow.id);
+            Ok(bow)
+        }
+        Err(e) => {
+            if let Err(cleanup) = db.delete_bow(bow.id).await {
+                error!(target: "arrow::archive", "cannot remove partially imported bow {}: '{}'", bow.id, cleanup);
+            }
+            Err(e)
+        }
+    }
+}
+
+async fn import_contents<D: ArrowDB>(
+    db: &D,
+    bow: &Bow,
+    archive: BowArchive,
+    series_names: &mut HashSet<String>,
+) -> Result<(), ArchiveError> {
+    let mut arrow_ids = HashMap::new();
+    for arrow in archive.arrows {
+        let old_id = arrow.id;
+        let arrow = db
+            .add_arrow(Arrow {
+                bow_id: bow.id,
+                ..arrow
+            })
+            .await?;
+        arrow_ids.insert(old_id, arrow.id);
+    }
+    for SeriesArchive { series, measures } in archive.series {
+        let name = unique_name(&series.name, series_names);
+        series_names.insert(name.clone());
+        let series = db
+            .add_measure_series(MeasureSeries {
+                name,
+                bow_id: bow.id,
+                ..series
+            })
+            .await?;
+        for MeasureArchive {
+            measure,
+            points,
+            result,
+        } in measures
+        {
+            let arrow_id = *arrow_ids.get(&measure.arrow_id).ok_or_else(|| {
+                ArchiveError::Invalid(format!(
+                    "measure {} uses unknown arrow {}",
+                    measure.id, measure.arrow_id
+                ))
+            })?;
+            let measure = db
+                .add_measure(Measure {
+                    measure_series_id: series.id,
+                    arrow_id,
+                    ..measure
+                })
+                .await?;
+            // not notified, the clients list the points of the new measure
+            db.add_measure_points(measure.id, points).await?;
+            if let Some(result) = result {
+                db.add_measure_result(MeasureResult {
+                    measure_id: measure.id,
+                    ..result
+                })
+                .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Appends " (2)", " (3)", ... to the name until it is not taken.
+fn unique_name(name: &str, taken: &HashSet<String>) -> String {
+    let mut unique = name.to_string();
+    let mut n = 1;
+    while taken.contains(&unique) {
+        n += 1;
+        unique = format!("{} ({})", name, n);
+    }
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::database::InMemoryArrowDB;
+    use time::OffsetDateTime;
+
+    async fn recorded_bow(db: &InMemoryArrowDB) -> Bow {
+        let bow = db
+            .add_bow(Bow {
+                id: -1,
+                name: "recurve".into(),
+                max_draw_distance: 0.8,
+                remainder_arrow_length: 0.1,
+            })
+            .await
+            .unwrap();
+        let series = db
+            .add_measure_series(MeasureSeries {
+                id: -1,
+                name: "series".into(),
+                rest_position: 0.2,
+                draw_distance: Some(0.5),
+                draw_force: None,
+                time: OffsetDateTime::now_utc(),
+                bow_id: bow.id,
+            })
+            .await
+            .unwrap();
+        let arrow = db
+            .add_arrow(Arrow {
+                id: -1,
+                name: Some("carbon".into()),
+                head_weight: None,
+                spline: None,
+                feather_length: None,
+                feather_type: None,
+                length: 0.75,
+                weight: 0.025,
+                bow_id: bow.id,
+            })
+            .await
+            .unwrap();
+        let measure = db
+            .add_measure(Measure {
+                id: -1,
+                measure_interval: 0.01,
+                measure_series_id: series.id,
+                arrow_id: arrow.id,
+            })
+            .await
+            .unwrap();
+        let points = (0..3)
+            .map(|i| MeasurePoint {
+                id: -1,
+                time: i * 10_000,
+                draw_distance: 0.2 + i as f64 * 0.1,
+                force: i as f64 * 50.0,
+                measure_id: measure.id,
+            })
+            .collect();
+        db.add_measure_points(measure.id, points).await.unwrap();
+        bow
+    }
+
+    #[tokio::test]
+    async fn archive_round_trip_renames_conflicts() {
+        let db = InMemoryArrowDB::new();
+        let bow = recorded_bow(&db).await;
+        let archive = export_bow(&db, bow.id).await.unwrap();
+        let json = serde_json::to_string(&archive).unwrap();
+
+        let imported = import_bow(&db, serde_json::from_str(&json).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(imported.name, "recurve (2)");
+        let copy = export_bow(&db, imported.id).await.unwrap();
+        assert_eq!(copy.series[0].series.name, "series (2)");
+        assert_eq!(copy.arrows[0].name.as_deref(), Some("carbon"));
+        let measure = &copy.series[0].measures[0];
+        assert_eq!(measure.measure.arrow_id, copy.arrows[0].id);
+        assert_eq!(measure.points.len(), 3);
+        assert!(measure
+            .points
+            .iter()
+            .all(|p| p.measure_id == measure.measure.id));
+    }
+
+    #[tokio::test]
+    async fn failed_import_is_removed() {
+        let db = InMemoryArrowDB::new();
+        let bow = recorded_bow(&db).await;
+        let mut archive = export_bow(&db, bow.id).await.unwrap();
+        archive.series[0].measures[0].measure.arrow_id = 99;
+
+        assert!(matches!(
+            import_bow(&db, archive.clone()).await,
+            Err(ArchiveError::Invalid(_))
+        ));
+        assert_eq!(db.list_bows().await.unwrap().len(), 1);
+
+        archive.version = ARCHIVE_VERSION + 1;
+        assert!(matches!(
+            import_bow(&db, archive).await,
+            Err(ArchiveError::Version(_))
+        ));
+    }
+}
@@ -0,0 +1,87 @@
+use crate::models::{MeasurePoint, MeasureResult};
+
+/// Energy in joule stored in the bow, integrated over the force-draw curve.
+/// Draw distances are taken relative to `rest_position`, points before it do not contribute.
+pub fn potential_energy(points: &[MeasurePoint], rest_position: f64) -> f64 {
+    let draw = |p: &MeasurePoint| (p.draw_distance - rest_position).max(0.0);
+    points
+        .windows(2)
+        .map(|w| (draw(&w[1]) - draw(&w[0])) * (w[0].force + w[1].force) / 2.0)
+        .sum()
+}
+
+/// Kinetic energy in joule of an arrow with `mass` in kilogram and `speed` in meters per second.
+pub fn kinetic_energy(mass: f64, speed: f64) -> f64 {
+    mass * speed * speed / 2.0
+}
+
+/// Computes the result of a shot from its measure points, ordered by time.
+pub fn evaluate(
+    measure_id: i32,
+    points: &[MeasurePoint],
+    rest_position: f64,
+    arrow_mass: f64,
+    arrow_speed: f64,
+) -> MeasureResult {
+    let potential_energy = potential_energy(points, rest_position);
+    let kinetic_energy = kinetic_energy(arrow_mass, arrow_speed);
+    let efficiency = if potential_energy > 0.0 {
+        kinetic_energy / potential_energy
+    } else {
+        0.0
+    };
+    MeasureResult {
+        id: -1,
+        measure_id,
+        arrow_speed,
+        potential_energy,
+        kinetic_energy,
+        efficiency,
+        wasted_energy: potential_energy - kinetic_energy,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(draw_distance: f64, force: f64) -> MeasurePoint {
+        MeasurePoint {
+            id: -1,
+            time: 0,
+            draw_distance,
+            force,
+            measure_id: 1,
+        }
+    }
+
+    #[test]
+    fn integrates_linear_curve_after_rest_position() {
+        // before the rest position the carriage does not touch the string
+        let points: Vec<_> = (0..=10)
+            .map(|i| {
+                let d = i as f64 * 0.1;
+                point(d, (d - 0.5).max(0.0) * 100.0)
+            })
+            .collect();
+        let energy = potential_energy(&points, 0.5);
+        assert!((energy - 12.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn hold_phase_adds_no_energy() {
+        let points = vec![point(0.0, 0.0), point(0.4, 40.0), point(0.4, 38.0)];
+        assert!((potential_energy(&points, 0.0) - 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn efficiency_of_shot() {
+        let points = vec![point(0.0, 0.0), point(0.5, 40.0)];
+        let result = evaluate(3, &points, 0.0, 0.02, 20.0);
+        assert_eq!(result.measure_id, 3);
+        assert!((result.potential_energy - 10.0).abs() < 1e-9);
+        assert!((result.kinetic_energy - 4.0).abs() < 1e-9);
+        assert!((result.efficiency - 0.4).abs() < 1e-9);
+        assert!((result.wasted_energy - 6.0).abs() < 1e-9);
+    }
+}
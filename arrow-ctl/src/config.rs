@@ -1,58 +1,171 @@
+use super::controller::{MAX_MEASURE_INTERVAL, MIN_MEASURE_INTERVAL};
+use super::export::ExportTarget;
+use super::models::Role;
+use arrow_hal::{HardwareConfig, SafetyLimits, SimulatedBow};
 use log::LevelFilter;
 use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+/// Longest configurable delay in seconds.
+const MAX_DURATION: f64 = 3600.0;
 
 pub struct CmdArgs {
     pub verbosity: LevelFilter,
     pub config_file: Option<String>,
     pub log_file: String,
+    pub simulate: bool,
+    pub migrate: bool,
+    pub pending_migrations: bool,
+    /// Replace the configured listen addresses if not empty.
+    pub addresses: Vec<IpAddr>,
+    pub port: Option<u16>,
+    pub tls: Option<TlsConfiguration>,
+    pub static_dir: Option<String>,
+    /// Task to run instead of the machine.
+    pub command: Option<Command>,
+}
+
+/// Tasks selected by subcommand, `output` is written to stdout if not set.
+pub enum Command {
+    /// Writes recorded data as CSV.
+    Export {
+        target: ExportTarget,
+        output: Option<String>,
+    },
+    /// Writes a bow with all its data as JSON archive.
+    Archive { bow_id: i32, output: Option<String> },
+    /// Stores the bow of a JSON archive.
+    Import { file: String },
+    /// Creates a user account.
+    AddUser { name: String, role: Role },
+    /// Writes the protocol schema, as TypeScript definitions if `typescript`.
+    Schema {
+        typescript: bool,
+        output: Option<String>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HardwareBackend {
+    Raspberry,
+    Simulated,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DBBackend {
+    Postgres,
+    Memory,
+    Sqlite,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 #[serde(default)]
 pub struct DBConfiguration {
+    pub backend: DBBackend,
     pub user: String,
     pub host: String,
     pub port: u32,
     pub db: String,
     pub password: String,
+    /// Database file of the sqlite backend.
+    pub path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct ServerConfiguration {
+    /// Addresses the webserver listens on, all with the same port.
+    pub addresses: Vec<IpAddr>,
+    pub port: u16,
+    /// Serves https and wss instead of http and ws if set.
+    pub tls: Option<TlsConfiguration>,
+    /// Clients must log in, viewers can only read data. Needs a database
+    /// backend keeping the accounts.
+    pub authentication: bool,
+    /// Directory with the built web front-end, served below `/`.
+    pub static_dir: Option<String>,
+}
+
+/// Paths of the PEM encoded certificate chain and private key.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsConfiguration {
+    pub cert: String,
+    pub key: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 #[serde(default)]
 pub struct Configuration {
     pub db: DBConfiguration,
+    pub server: ServerConfiguration,
     pub draw_measure_interval: f64,
+    pub live_update_interval: f64,
+    pub draw_speed: f64,
     pub hold_time: f64,
     pub speed_measure_distance: f64,
     pub distance_per_rotation: f64,
     pub max_draw_distance: f64,
     pub max_draw_force: f64,
+    pub hardware: HardwareBackend,
+    pub simulated_bow: SimulatedBow,
 }
 
 impl Default for DBConfiguration {
     fn default() -> Self {
         Self {
+            backend: DBBackend::Postgres,
             user: "arrow".into(),
             host: "localhost".into(),
             port: 5432,
             db: "arrow".into(),
             password: "14b8330c76fe611f9a62618f7dd37cd4".into(),
+            path: "/var/lib/arrow/arrow.db".into(),
+        }
+    }
+}
+
+impl Default for ServerConfiguration {
+    fn default() -> Self {
+        Self {
+            addresses: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            port: 6000,
+            tls: None,
+            authentication: false,
+            static_dir: None,
         }
     }
 }
 
+impl ServerConfiguration {
+    pub fn socket_addrs(&self) -> Vec<SocketAddr> {
+        self.addresses
+            .iter()
+            .map(|ip| SocketAddr::new(*ip, self.port))
+            .collect()
+    }
+}
+
 impl Default for Configuration {
     fn default() -> Self {
         Self {
             db: Default::default(),
+            server: Default::default(),
             draw_measure_interval: 10e-3,
+            live_update_interval: 40e-3,
+            draw_speed: 5e-2,
             hold_time: 100e-3,
             speed_measure_distance: 20e-2,
             distance_per_rotation: 30e-3,
             max_draw_distance: 0.85,
             max_draw_force: 30.0,
+            hardware: HardwareBackend::Raspberry,
+            simulated_bow: Default::default(),
         }
     }
 }
@@ -63,6 +176,75 @@ impl Default for CmdArgs {
             verbosity: LevelFilter::Warn,
             config_file: None,
             log_file: "/var/log/arrow.log".into(),
+            simulate: false,
+            migrate: false,
+            pending_migrations: false,
+            addresses: Vec::new(),
+            port: None,
+            tls: None,
+            static_dir: None,
+            command: None,
+        }
+    }
+}
+
+impl Configuration {
+    /// Refuses timings the controller cannot work with.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_MEASURE_INTERVAL..=MAX_MEASURE_INTERVAL).contains(&self.draw_measure_interval) {
+            return Err(format!(
+                "drawMeasureInterval must be between {} and {} s.",
+                MIN_MEASURE_INTERVAL, MAX_MEASURE_INTERVAL
+            ));
+        }
+        for (name, value) in [
+            ("liveUpdateInterval", self.live_update_interval),
+            ("holdTime", self.hold_time),
+        ] {
+            if !(0.0..=MAX_DURATION).contains(&value) {
+                return Err(format!(
+                    "{} must be between 0 and {} s.",
+                    name, MAX_DURATION
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn hardware_config(&self) -> HardwareConfig {
+        HardwareConfig {
+            distance_per_rotation: self.distance_per_rotation,
+            speed_measure_distance: self.speed_measure_distance,
+            limits: SafetyLimits {
+                max_draw_distance: self.max_draw_distance,
+                max_draw_force: self.max_draw_force,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timings_are_validated() {
+        assert!(Configuration::default().validate().is_ok());
+        for config in [
+            Configuration {
+                draw_measure_interval: 1e-12,
+                ..Default::default()
+            },
+            Configuration {
+                hold_time: -1.0,
+                ..Default::default()
+            },
+            Configuration {
+                live_update_interval: f64::NAN,
+                ..Default::default()
+            },
+        ] {
+            assert!(config.validate().is_err());
         }
     }
 }
@@ -1,65 +1,1022 @@
-use crate::message;
+use crate::calculation;
+use crate::config::{Configuration, HardwareBackend};
+use crate::message::{ControlMessage, ControlReceiver, ControlReply, MachineCommand, WSUpdate};
+use crate::models::{MachineStatus, Measure, MeasurePoint, MeasureResult, MeasureSeries};
 use crate::server::database::ArrowDB;
 use crate::server::Webserver;
+use arrow_hal::{HalError, Hardware, SafetyLimits, Sample, SimulatedHardware};
+use futures::future::BoxFuture;
+use log::{error, info, warn};
 use std::error::Error;
-use tokio::sync::{broadcast, mpsc};
-use tokio::time::interval;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{interval, Instant, Interval};
 
-const CONTROL_CHANNEL_SIZE: usize = 32;
+pub const CONTROL_CHANNEL_SIZE: usize = 32;
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+/// Time to wait for the arrow to pass the light gates after the release.
+const ARROW_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Interval of the safety checks while no measure is running.
+const SUPERVISION_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Accepted measure intervals in seconds.
+pub const MIN_MEASURE_INTERVAL: f64 = 1e-4;
+pub const MAX_MEASURE_INTERVAL: f64 = 10.0;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ControlError {
+    #[error("machine is busy ({0:?}).")]
+    Busy(MachineStatus),
+
+    #[error("machine is in error state: {0}, clear the error first.")]
+    Fault(String),
+
+    #[error("measure series {0} has neither a draw distance nor a draw force to stop at.")]
+    NoEndCondition(i32),
+
+    #[error(
+        "measure interval of {0} s is outside of {} to {} s.",
+        MIN_MEASURE_INTERVAL,
+        MAX_MEASURE_INTERVAL
+    )]
+    BadInterval(f32),
+
+    #[error("no drivers available for the {0:?} hardware backend, use the simulated backend ('--simulate').")]
+    NoDriver(HardwareBackend),
+
+    #[error("hardware error: {0}.")]
+    Hardware(#[from] HalError),
+
+    #[error(transparent)]
+    Sql(#[from] sqlx::Error),
+}
+
+/// Measure currently being executed.
+struct Shot {
+    measure: Measure,
+    series: MeasureSeries,
+    interval: Duration,
+    started: Instant,
+    arrow_speed: Option<f64>,
+    points: Vec<MeasurePoint>,
+    /// Number of points already stored and streamed to the clients.
+    streamed: usize,
+    last_frame: Instant,
+}
+
+impl Shot {
+    fn end_reached(&self, sample: &Sample) -> bool {
+        let distance_reached = matches!(
+            self.series.draw_distance,
+            Some(d) if sample.draw_distance >= d as f64
+        );
+        let force_reached = matches!(
+            self.series.draw_force,
+            Some(f) if sample.force >= f as f64
+        );
+        distance_reached || force_reached
+    }
+
+    /// Points recorded since the last frame.
+    fn next_frame(&mut self) -> Option<Vec<MeasurePoint>> {
+        if self.streamed == self.points.len() {
+            return None;
+        }
+        let points = self.points[self.streamed..].to_vec();
+        self.streamed = self.points.len();
+        self.last_frame = Instant::now();
+        Some(points)
     }
 }
 
+enum Record {
+    Points(i32, Vec<MeasurePoint>),
+    Result(MeasureResult),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Persists the recorded data in the background, so database round-trips
+/// do not delay the sampling.
+struct Writer {
+    tx: mpsc::UnboundedSender<Record>,
+}
+
+impl Writer {
+    fn spawn<F: ArrowDB + 'static>(server: Webserver<F>) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(record) = rx.recv().await {
+                let result = match record {
+                    Record::Points(measure_id, points) => server
+                        .db()
+                        .add_measure_points(measure_id, points)
+                        .await
+                        .map(|_| ()),
+                    Record::Result(result) => {
+                        server.db().add_measure_result(result).await.map(|_| ())
+                    }
+                    Record::Flush(done) => {
+                        let _ = done.send(());
+                        Ok(())
+                    }
+                };
+                if let Err(e) = result {
+                    error!(target: "arrow::hw", "cannot store measure data: '{}'", e);
+                    server
+                        .broadcast(WSUpdate::Error(format!("cannot store measure data: {}", e)))
+                        .await;
+                }
+            }
+        });
+        Self { tx }
+    }
+
+    fn store(&self, record: Record) {
+        if self.tx.send(record).is_err() {
+            error!(target: "arrow::hw", "measure data writer stopped");
+        }
+    }
+
+    /// Waits until all previously stored records are written.
+    async fn flush(&self) {
+        let (done, wait) = oneshot::channel();
+        self.store(Record::Flush(done));
+        let _ = wait.await;
+    }
+}
+
+enum State {
+    Idle,
+    Drawing(Shot),
+    Holding(Shot, Instant),
+    Released(Shot, Instant),
+    Retracting(Shot),
+}
+
+impl State {
+    fn into_shot(self) -> Option<Shot> {
+        match self {
+            State::Idle => None,
+            State::Drawing(shot)
+            | State::Holding(shot, _)
+            | State::Released(shot, _)
+            | State::Retracting(shot) => Some(shot),
+        }
+    }
+}
+
+/// Unwraps the result of a measure step. On errors the shot is put back into
+/// its state, so the fault handler still stores its last points.
+macro_rules! or_abort {
+    ($ctl:ident, $result:expr, $state:expr) => {
+        match $result {
+            Ok(value) => value,
+            Err(e) => {
+                $ctl.state = $state;
+                return Err(ControlError::from(e));
+            }
+        }
+    };
+}
+
 pub struct Controller<F>
 where
     F: ArrowDB,
 {
-    pub ctl_rx: broadcast::Receiver<message::ControlMessage>,
-    pub ctl_tx: mpsc::Sender<message::ControlMessage>,
     pub server: Webserver<F>,
-    ctl_send_tx: broadcast::Sender<message::ControlMessage>,
-    ctl_recv_rx: mpsc::Receiver<message::ControlMessage>,
+    hardware: Hardware,
+    config: Configuration,
+    state: State,
+    status: MachineStatus,
+    fault: Option<String>,
+    ticker: Option<Interval>,
+    supervision: Interval,
+    writer: Writer,
+    /// Set by `Shutdown`, the loop ends after the current message.
+    stopping: bool,
+    ctl_recv_rx: mpsc::Receiver<ControlMessage>,
+    emergency_rx: mpsc::Receiver<ControlReply<MachineStatus>>,
 }
 
-impl<F: ArrowDB> Controller<F> {
-    pub fn new(server: Webserver<F>) -> Result<Self, Box<dyn Error>> {
-        let (recv_tx, recv_rx) = tokio::sync::mpsc::channel(CONTROL_CHANNEL_SIZE);
-        let (send_tx, send_rx) = tokio::sync::broadcast::channel(CONTROL_CHANNEL_SIZE);
+impl<F: ArrowDB + 'static> Controller<F> {
+    pub fn new(
+        server: Webserver<F>,
+        hardware: Hardware,
+        config: &Configuration,
+        ctl_receiver: ControlReceiver,
+    ) -> Result<Self, Box<dyn Error>> {
         Ok(Self {
-            ctl_tx: recv_tx,
-            ctl_recv_rx: recv_rx,
+            writer: Writer::spawn(server.clone()),
             server,
-            ctl_send_tx: send_tx,
-            ctl_rx: send_rx,
+            hardware,
+            config: config.clone(),
+            state: State::Idle,
+            status: MachineStatus::Pause,
+            fault: None,
+            ticker: None,
+            supervision: interval(SUPERVISION_INTERVAL),
+            stopping: false,
+            ctl_recv_rx: ctl_receiver.control,
+            emergency_rx: ctl_receiver.emergency,
         })
     }
 
     pub async fn start(&mut self) {
-        let _ = self.ctl_send_tx;
-        /*
-        let srv = self.server.clone();
-        tokio::spawn(async move {
-            let mut int = interval(std::time::Duration::from_secs(3));
-            int.tick().await;
-            loop {
-                int.tick().await;
-                srv.broadcast(message::WSUpdate::Alive)
-                    .await;
+        if let Err(e) = self.hardware.start() {
+            error!(target: "arrow::hw", "cannot start hardware: '{}'", e);
+        }
+        loop {
+            tokio::select! {
+                biased;
+                Some(reply) = self.emergency_rx.recv() => self.emergency_stop(reply).await,
+                msg = self.ctl_recv_rx.recv() => match msg {
+                    Some(ControlMessage::StartMeasure(measure, reply)) => {
+                        let result = self.start_measure(measure).await;
+                        let _ = reply.send(result);
+                    }
+                    Some(ControlMessage::Command(command, reply)) => {
+                        let result = self.execute(command).await;
+                        let _ = reply.send(result);
+                    }
+                    Some(ControlMessage::Terminate) | None => break,
+                },
+                _ = next_tick(&mut self.ticker) => self.step().await,
+                _ = self.supervision.tick(), if self.is_supervising() => self.supervise().await,
+            }
+            if self.stopping {
+                break;
             }
+        }
+        if let Some(mut shot) = std::mem::replace(&mut self.state, State::Idle).into_shot() {
+            warn!(target: "arrow::hw", "terminating during a measure, stopping motor");
+            let _ = self.hardware.stop();
+            self.send_frame(&mut shot).await;
+        }
+        self.writer.flush().await;
+    }
+
+    fn is_supervising(&self) -> bool {
+        matches!(self.state, State::Idle) && self.fault.is_none()
+    }
+
+    /// Status of the machine while no measure is running.
+    fn idle_status(&self) -> MachineStatus {
+        match &self.fault {
+            Some(reason) => MachineStatus::Error(reason.clone()),
+            None => MachineStatus::Pause,
+        }
+    }
+
+    /// Stops the motor, aborts a running measure and latches the error state.
+    async fn fault(&mut self, reason: String) {
+        error!(target: "arrow::hw", "{}", reason);
+        if let Err(e) = self.hardware.stop() {
+            error!(target: "arrow::hw", "cannot stop motor: '{}'", e);
+        }
+        self.hardware.reset_limits();
+        if let Some(mut shot) = std::mem::replace(&mut self.state, State::Idle).into_shot() {
+            self.send_frame(&mut shot).await;
+        }
+        self.ticker = None;
+        self.fault = Some(reason);
+        self.set_status(self.idle_status()).await;
+    }
+
+    async fn supervise(&mut self) {
+        if let Err(e) = self.hardware.sample() {
+            self.fault(format!("safety supervisor: {}", e)).await;
+        }
+    }
+
+    async fn emergency_stop(&mut self, reply: ControlReply<MachineStatus>) {
+        info!(target: "arrow::hw", "executing command {:?}", MachineCommand::EmergencyStop);
+        self.fault("emergency stop".into()).await;
+        let _ = reply.send(Ok(self.status.clone()));
+    }
+
+    /// Awaits a database request. An emergency stop arriving meanwhile is
+    /// executed right away and aborts the request.
+    async fn query<T>(
+        &mut self,
+        request: impl for<'a> FnOnce(&'a F) -> BoxFuture<'a, Result<T, sqlx::Error>>,
+    ) -> Result<T, ControlError> {
+        let reply = {
+            let request = request(self.server.db());
+            tokio::select! {
+                biased;
+                Some(reply) = self.emergency_rx.recv() => reply,
+                result = request => return Ok(result?),
+            }
+        };
+        self.emergency_stop(reply).await;
+        Err(ControlError::Fault("emergency stop".into()))
+    }
+
+    async fn set_status(&mut self, status: MachineStatus) {
+        if self.status != status {
+            info!(target: "arrow::hw", "machine status: {:?}", status);
+            self.status = status.clone();
+            self.server.broadcast(WSUpdate::Status(status)).await;
+        }
+    }
+
+    async fn start_measure(&mut self, measure: Measure) -> Result<Measure, ControlError> {
+        if !matches!(self.state, State::Idle) {
+            return Err(ControlError::Busy(self.status.clone()));
+        }
+        if let Some(reason) = &self.fault {
+            return Err(ControlError::Fault(reason.clone()));
+        }
+        let measure_interval = if measure.measure_interval > 0.0 {
+            measure.measure_interval as f64
+        } else {
+            self.config.draw_measure_interval
+        };
+        if !(MIN_MEASURE_INTERVAL..=MAX_MEASURE_INTERVAL).contains(&measure_interval) {
+            return Err(ControlError::BadInterval(measure.measure_interval));
+        }
+        let series = self
+            .query(|db| db.get_measure_series(measure.measure_series_id))
+            .await?;
+        if series.draw_distance.is_none() && series.draw_force.is_none() {
+            return Err(ControlError::NoEndCondition(series.id));
+        }
+        let bow = self.query(|db| db.get_bow(series.bow_id)).await?;
+        let limits = self.hardware.config().limits.min(SafetyLimits {
+            max_draw_distance: bow.max_draw_distance as f64,
+            max_draw_force: f64::INFINITY,
         });
-        */
-        while let Some(msg) = self.ctl_recv_rx.recv().await {
-            use message::ControlMessage::*;
-            match msg {
-                Terminate => {
-                    break;
+        limits.check(&Sample {
+            draw_distance: series.draw_distance.unwrap_or_default() as f64,
+            force: series.draw_force.unwrap_or_default() as f64,
+        })?;
+        let measure = self
+            .query(|db| {
+                db.add_measure(Measure {
+                    measure_interval: measure_interval as f32,
+                    ..measure
+                })
+            })
+            .await?;
+        info!(target: "arrow::hw", "starting measure {}", measure.id);
+
+        let shot = Shot {
+            measure: measure.clone(),
+            series,
+            interval: Duration::from_secs_f64(measure_interval),
+            started: Instant::now(),
+            arrow_speed: None,
+            points: Vec::new(),
+            streamed: 0,
+            last_frame: Instant::now(),
+        };
+        self.hardware.set_limits(limits);
+        self.ticker = Some(interval(shot.interval));
+        self.state = State::Drawing(shot);
+        self.set_status(MachineStatus::Drawing).await;
+        Ok(measure)
+    }
+
+    async fn execute(&mut self, command: MachineCommand) -> Result<MachineStatus, ControlError> {
+        info!(target: "arrow::hw", "executing command {:?}", command);
+        match command {
+            MachineCommand::EmergencyStop => {
+                self.fault("emergency stop".into()).await;
+                return Ok(self.status.clone());
+            }
+            MachineCommand::Shutdown => {
+                // the controller stops itself, a `Terminate` would queue up
+                // behind the pending requests it never gets to
+                self.stopping = true;
+                if self.server.shutdown_tx.send(()).await.is_err() {
+                    error!(target: "arrow::hw", "webserver already shut down");
+                }
+                return Ok(self.status.clone());
+            }
+            _ if !matches!(self.state, State::Idle) => {
+                return Err(ControlError::Busy(self.status.clone()));
+            }
+            _ => (),
+        }
+        let result = match command {
+            MachineCommand::Calibrate => self.hardware.calibrate().map_err(ControlError::from),
+            MachineCommand::Reset => self
+                .hardware
+                .stop()
+                .and_then(|_| self.hardware.home())
+                .map_err(ControlError::from),
+            MachineCommand::Restart => create_hardware(&self.config).and_then(|hardware| {
+                self.hardware = hardware;
+                Ok(self.hardware.start()?)
+            }),
+            MachineCommand::ClearError => self
+                .hardware
+                .sample()
+                .map(|_| self.fault = None)
+                .map_err(ControlError::from),
+            MachineCommand::EmergencyStop | MachineCommand::Shutdown => unreachable!(),
+        };
+        match result {
+            Ok(()) => {
+                self.set_status(self.idle_status()).await;
+                Ok(self.status.clone())
+            }
+            Err(e) => {
+                self.fault(format!("command {:?} failed: {}", command, e))
+                    .await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn step(&mut self) {
+        if let Err(e) = self.try_step().await {
+            self.fault(format!("measure aborted: {}", e)).await;
+        }
+    }
+
+    async fn try_step(&mut self) -> Result<(), ControlError> {
+        let state = std::mem::replace(&mut self.state, State::Idle);
+        self.state = match state {
+            State::Idle => State::Idle,
+            State::Drawing(mut shot) => {
+                let sample = or_abort!(self, self.record(&mut shot).await, State::Drawing(shot));
+                if shot.end_reached(&sample) {
+                    or_abort!(self, self.hardware.stop(), State::Drawing(shot));
+                    self.set_status(MachineStatus::Holding).await;
+                    let hold_time = Duration::from_secs_f64(self.config.hold_time);
+                    State::Holding(shot, Instant::now() + hold_time)
+                } else {
+                    let step = self.config.draw_speed * shot.interval.as_secs_f64();
+                    or_abort!(self, self.hardware.draw(step), State::Drawing(shot));
+                    State::Drawing(shot)
+                }
+            }
+            State::Holding(mut shot, until) => {
+                or_abort!(
+                    self,
+                    self.record(&mut shot).await,
+                    State::Holding(shot, until)
+                );
+                if Instant::now() >= until {
+                    let released = self
+                        .hardware
+                        .arm_light_gates()
+                        .and_then(|_| self.hardware.release());
+                    or_abort!(self, released, State::Holding(shot, until));
+                    self.send_frame(&mut shot).await;
+                    self.set_status(MachineStatus::Shooting).await;
+                    State::Released(shot, Instant::now() + ARROW_TIMEOUT)
+                } else {
+                    State::Holding(shot, until)
                 }
             }
+            State::Released(mut shot, timeout) => {
+                let speed = self
+                    .hardware
+                    .sample()
+                    .and_then(|_| self.hardware.arrow_speed());
+                shot.arrow_speed = or_abort!(self, speed, State::Released(shot, timeout));
+                if shot.arrow_speed.is_none() && Instant::now() < timeout {
+                    State::Released(shot, timeout)
+                } else {
+                    self.set_status(MachineStatus::Retracting).await;
+                    State::Retracting(shot)
+                }
+            }
+            State::Retracting(shot) => {
+                // retracts in steps to keep checking the limits, homing
+                // finishes the last step
+                let sample = or_abort!(self, self.hardware.sample(), State::Retracting(shot));
+                let step = self.config.draw_speed * shot.interval.as_secs_f64();
+                if sample.draw_distance > step {
+                    or_abort!(self, self.hardware.draw(-step), State::Retracting(shot));
+                    State::Retracting(shot)
+                } else {
+                    self.finish(shot).await?;
+                    State::Idle
+                }
+            }
+        };
+        Ok(())
+    }
+
+    /// Samples the sensors and records the reading as measure point. The
+    /// points are stored and streamed to the clients in batches, once per
+    /// live update interval.
+    async fn record(&mut self, shot: &mut Shot) -> Result<Sample, ControlError> {
+        let sample = self.hardware.sample()?;
+        let point = MeasurePoint {
+            id: -1,
+            time: shot.started.elapsed().as_micros() as i64,
+            draw_distance: sample.draw_distance,
+            force: sample.force,
+            measure_id: shot.measure.id,
+        };
+        shot.points.push(point);
+        let frame = Duration::from_secs_f64(self.config.live_update_interval);
+        if shot.last_frame.elapsed() >= frame {
+            self.send_frame(shot).await;
+        }
+        Ok(sample)
+    }
+
+    /// Stores the points recorded since the last frame as one batch and
+    /// streams them to the clients.
+    async fn send_frame(&mut self, shot: &mut Shot) {
+        if let Some(points) = shot.next_frame() {
+            let measure_id = shot.measure.id;
+            self.writer
+                .store(Record::Points(measure_id, points.clone()));
+            self.server
+                .broadcast(WSUpdate::LiveMeasurePoints { measure_id, points })
+                .await;
+        }
+    }
+
+    async fn finish(&mut self, shot: Shot) -> Result<(), ControlError> {
+        self.ticker = None;
+        self.hardware.home()?;
+        self.hardware.reset_limits();
+        self.set_status(MachineStatus::Pause).await;
+
+        match shot.arrow_speed {
+            Some(speed) => {
+                info!(
+                    target: "arrow::hw",
+                    "measure {} finished, arrow speed {:.2} m/s", shot.measure.id, speed
+                );
+                self.store_result(&shot, speed).await?;
+            }
+            None => warn!(
+                target: "arrow::hw",
+                "measure {} finished, arrow not detected by light gates", shot.measure.id
+            ),
+        }
+        Ok(())
+    }
+
+    async fn store_result(&mut self, shot: &Shot, arrow_speed: f64) -> Result<(), ControlError> {
+        let arrow = self.query(|db| db.get_arrow(shot.measure.arrow_id)).await?;
+        let result = calculation::evaluate(
+            shot.measure.id,
+            &shot.points,
+            shot.series.rest_position as f64,
+            arrow.weight as f64,
+            arrow_speed,
+        );
+        info!(
+            target: "arrow::hw",
+            "measure {}: efficiency {:.1}%, wasted energy {:.2} J",
+            shot.measure.id,
+            result.efficiency * 100.0,
+            result.wasted_energy
+        );
+        self.writer.store(Record::Result(result));
+        Ok(())
+    }
+}
+
+async fn next_tick(ticker: &mut Option<Interval>) {
+    match ticker {
+        Some(t) => {
+            t.tick().await;
+        }
+        None => futures::future::pending().await,
+    }
+}
+
+pub fn create_hardware(config: &Configuration) -> Result<Hardware, ControlError> {
+    match config.hardware {
+        HardwareBackend::Simulated => {
+            info!(target: "arrow::hw", "using simulated hardware");
+            let sim =
+                SimulatedHardware::new(config.hardware_config(), config.simulated_bow.clone());
+            Ok(sim.into_hardware())
+        }
+        backend => Err(ControlError::NoDriver(backend)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{control_channel, ControlSender};
+    use crate::models::{Arrow, Bow, MeasureResult};
+    use crate::server::database::traits::MockDB;
+    use crate::server::database::InMemoryArrowDB;
+    use std::sync::{Arc, Mutex};
+    use time::OffsetDateTime;
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+
+    type Shared<T> = Arc<Mutex<T>>;
+
+    #[test]
+    fn it_works() {
+        assert_eq!(2 + 2, 4);
+    }
+
+    fn config() -> Configuration {
+        Configuration {
+            draw_speed: 2.0,
+            hardware: HardwareBackend::Simulated,
+            ..Default::default()
+        }
+    }
+
+    /// Mock database without any data, its clone is used by the writer.
+    fn empty_db() -> MockDB {
+        let mut db = MockDB::new();
+        db.expect_clone().returning(MockDB::new);
+        db
+    }
+
+    /// Mock database holding a single bow, series and arrow.
+    fn mock_db(
+        draw_distance: f32,
+    ) -> (
+        MockDB,
+        Shared<Vec<MeasurePoint>>,
+        Shared<Option<MeasureResult>>,
+    ) {
+        let points = Arc::new(Mutex::new(Vec::new()));
+        let result = Arc::new(Mutex::new(None));
+        let mut db = MockDB::new();
+        db.expect_get_measure_series().returning(move |id| {
+            Ok(MeasureSeries {
+                id,
+                name: "series".into(),
+                rest_position: 0.2,
+                draw_distance: Some(draw_distance),
+                draw_force: None,
+                time: OffsetDateTime::now_utc(),
+                bow_id: 1,
+            })
+        });
+        db.expect_get_bow().returning(|id| {
+            Ok(Bow {
+                id,
+                name: "bow".into(),
+                max_draw_distance: 0.8,
+                remainder_arrow_length: 0.1,
+            })
+        });
+        db.expect_add_measure()
+            .returning(|m| Ok(Measure { id: 7, ..m }));
+        db.expect_get_arrow().returning(|id| {
+            Ok(Arrow {
+                id,
+                name: None,
+                head_weight: None,
+                spline: None,
+                feather_length: None,
+                feather_type: None,
+                length: 0.7,
+                weight: 0.025,
+                bow_id: 1,
+            })
+        });
+        let (points_c, result_c) = (points.clone(), result.clone());
+        db.expect_clone().returning(move || {
+            let mut writer = MockDB::new();
+            let points_c = points_c.clone();
+            writer.expect_add_measure_points().returning(move |_, p| {
+                points_c.lock().unwrap().extend(p.iter().cloned());
+                Ok(p)
+            });
+            let result_c = result_c.clone();
+            writer.expect_add_measure_result().returning(move |r| {
+                *result_c.lock().unwrap() = Some(r.clone());
+                Ok(r)
+            });
+            writer
+        });
+        (db, points, result)
+    }
+
+    fn spawn<D: ArrowDB + 'static>(
+        config: &Configuration,
+        db: D,
+    ) -> (
+        SimulatedHardware,
+        ControlSender,
+        mpsc::Receiver<()>,
+        JoinHandle<()>,
+    ) {
+        let sim = SimulatedHardware::new(config.hardware_config(), config.simulated_bow.clone());
+        let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
+        let (ctl_tx, ctl_rx) = control_channel(CONTROL_CHANNEL_SIZE);
+        let server = Webserver::new(shutdown_tx, ctl_tx.clone(), db);
+        let mut ctl = Controller::new(server, sim.clone().into_hardware(), config, ctl_rx).unwrap();
+        let handle = tokio::spawn(async move { ctl.start().await });
+        (sim, ctl_tx, shutdown_rx, handle)
+    }
+
+    async fn command(
+        ctl_tx: &ControlSender,
+        command: MachineCommand,
+    ) -> Result<MachineStatus, ControlError> {
+        let (tx, rx) = oneshot::channel();
+        let _ = ctl_tx.send(ControlMessage::Command(command, tx)).await;
+        rx.await.unwrap()
+    }
+
+    async fn start_measure(ctl_tx: &ControlSender) -> Result<Measure, ControlError> {
+        let (tx, rx) = oneshot::channel();
+        let measure = Measure {
+            id: -1,
+            measure_interval: 0.0,
+            measure_series_id: 1,
+            arrow_id: 1,
+        };
+        let _ = ctl_tx.send(ControlMessage::StartMeasure(measure, tx)).await;
+        rx.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn commands_report_status() {
+        let (_sim, ctl_tx, mut shutdown_rx, handle) = spawn(&config(), empty_db());
+
+        for c in [
+            MachineCommand::Calibrate,
+            MachineCommand::Reset,
+            MachineCommand::Restart,
+        ] {
+            assert_eq!(command(&ctl_tx, c).await.unwrap(), MachineStatus::Pause);
         }
+
+        assert!(command(&ctl_tx, MachineCommand::Shutdown).await.is_ok());
+        assert!(shutdown_rx.recv().await.is_some());
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn shutdown_with_full_queue() {
+        let (_sim, ctl_tx, mut shutdown_rx, handle) = spawn(&config(), empty_db());
+        let mut replies = Vec::new();
+        for c in std::iter::once(MachineCommand::Shutdown)
+            .chain((0..CONTROL_CHANNEL_SIZE).map(|_| MachineCommand::Calibrate))
+        {
+            let (tx, rx) = oneshot::channel();
+            replies.push(rx);
+            let ctl_tx = ctl_tx.clone();
+            tokio::spawn(async move { ctl_tx.send(ControlMessage::Command(c, tx)).await });
+        }
+        let stopped = async move {
+            assert!(shutdown_rx.recv().await.is_some());
+            handle.await.unwrap();
+        };
+        tokio::time::timeout(Duration::from_secs(5), stopped)
+            .await
+            .expect("controller did not stop");
+    }
+
+    #[tokio::test]
+    async fn measure_runs_full_cycle() {
+        tokio::time::pause();
+        let config = config();
+        let (db, points, result) = mock_db(0.6);
+        let (sim, ctl_tx, _shutdown_rx, handle) = spawn(&config, db);
+
+        let measure = start_measure(&ctl_tx).await.unwrap();
+        assert_eq!(measure.id, 7);
+        assert_eq!(
+            measure.measure_interval,
+            config.draw_measure_interval as f32
+        );
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let _ = ctl_tx.send(ControlMessage::Terminate).await;
+        handle.await.unwrap();
+
+        assert!(points.lock().unwrap().len() > 30);
+        assert_eq!(sim.position(), 0.0);
+
+        // the simulated bow transfers a fixed share of its energy to the arrow
+        let result: MeasureResult = result.lock().unwrap().take().unwrap();
+        assert_eq!(result.measure_id, 7);
+        assert!((result.efficiency - config.simulated_bow.efficiency).abs() < 0.05);
+    }
+
+    #[tokio::test]
+    async fn measure_is_stored_in_memory_db() {
+        tokio::time::pause();
+        let db = InMemoryArrowDB::new();
+        let bow = db
+            .add_bow(Bow {
+                id: -1,
+                name: "bow".into(),
+                max_draw_distance: 0.8,
+                remainder_arrow_length: 0.1,
+            })
+            .await
+            .unwrap();
+        let series = db
+            .add_measure_series(MeasureSeries {
+                id: -1,
+                name: "series".into(),
+                rest_position: 0.2,
+                draw_distance: Some(0.6),
+                draw_force: None,
+                time: OffsetDateTime::now_utc(),
+                bow_id: bow.id,
+            })
+            .await
+            .unwrap();
+        db.add_arrow(Arrow {
+            id: -1,
+            name: None,
+            head_weight: None,
+            spline: None,
+            feather_length: None,
+            feather_type: None,
+            length: 0.7,
+            weight: 0.025,
+            bow_id: bow.id,
+        })
+        .await
+        .unwrap();
+        let (_sim, ctl_tx, _shutdown_rx, handle) = spawn(&config(), db.clone());
+
+        let measure = start_measure(&ctl_tx).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let _ = ctl_tx.send(ControlMessage::Terminate).await;
+        handle.await.unwrap();
+
+        assert!(db.list_measure_points(measure.id).await.unwrap().len() > 30);
+        let results = db.list_measure_results(series.id).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].measure_id, measure.id);
+    }
+
+    #[tokio::test]
+    async fn end_condition_beyond_bow_limit_is_refused() {
+        let (db, _, _) = mock_db(0.82);
+        let (_sim, ctl_tx, _shutdown_rx, _handle) = spawn(&config(), db);
+        assert!(matches!(
+            start_measure(&ctl_tx).await,
+            Err(ControlError::Hardware(HalError::LimitExceeded(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn series_without_end_condition_is_refused() {
+        let mut db = MockDB::new();
+        db.expect_get_measure_series().returning(|id| {
+            Ok(MeasureSeries {
+                id,
+                name: "series".into(),
+                rest_position: 0.2,
+                draw_distance: None,
+                draw_force: None,
+                time: OffsetDateTime::now_utc(),
+                bow_id: 1,
+            })
+        });
+        db.expect_clone().returning(MockDB::new);
+        let (sim, ctl_tx, _shutdown_rx, _handle) = spawn(&config(), db);
+        assert!(matches!(
+            start_measure(&ctl_tx).await,
+            Err(ControlError::NoEndCondition(1))
+        ));
+        assert_eq!(sim.position(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn measure_interval_is_checked() {
+        let (sim, ctl_tx, _shutdown_rx, _handle) = spawn(&config(), empty_db());
+        for measure_interval in [1e-12, 1e30, f32::INFINITY] {
+            let (tx, rx) = oneshot::channel();
+            let measure = Measure {
+                id: -1,
+                measure_interval,
+                measure_series_id: 1,
+                arrow_id: 1,
+            };
+            let _ = ctl_tx.send(ControlMessage::StartMeasure(measure, tx)).await;
+            assert!(matches!(
+                rx.await.unwrap(),
+                Err(ControlError::BadInterval(_))
+            ));
+        }
+        assert_eq!(sim.position(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn force_limit_latches_error() {
+        tokio::time::pause();
+        let config = Configuration {
+            max_draw_force: 10.0,
+            ..config()
+        };
+        let (db, _, _) = mock_db(0.7);
+        let (sim, ctl_tx, _shutdown_rx, _handle) = spawn(&config, db);
+
+        start_measure(&ctl_tx).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(sim.position() < 0.7);
+        assert!(matches!(
+            start_measure(&ctl_tx).await,
+            Err(ControlError::Fault(_))
+        ));
+
+        // the bow is still drawn beyond the limit
+        assert!(command(&ctl_tx, MachineCommand::ClearError).await.is_err());
+        assert!(command(&ctl_tx, MachineCommand::Reset).await.is_ok());
+        assert_eq!(
+            command(&ctl_tx, MachineCommand::ClearError).await.unwrap(),
+            MachineStatus::Pause
+        );
+    }
+
+    #[tokio::test]
+    async fn emergency_stop_interrupts_measure() {
+        tokio::time::pause();
+        let (db, _, _) = mock_db(0.7);
+        let (sim, ctl_tx, _shutdown_rx, _handle) = spawn(&config(), db);
+
+        start_measure(&ctl_tx).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let status = command(&ctl_tx, MachineCommand::EmergencyStop)
+            .await
+            .unwrap();
+        assert_eq!(status, MachineStatus::Error("emergency stop".into()));
+
+        let position = sim.position();
+        assert!(position > 0.0);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(sim.position(), position);
+        assert!(matches!(
+            start_measure(&ctl_tx).await,
+            Err(ControlError::Fault(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn emergency_stop_overtakes_queued_requests() {
+        let (db, _, _) = mock_db(0.7);
+        let (sim, ctl_tx, _shutdown_rx, _handle) = spawn(&config(), db);
+
+        let (tx, started) = oneshot::channel();
+        let measure = Measure {
+            id: -1,
+            measure_interval: 0.0,
+            measure_series_id: 1,
+            arrow_id: 1,
+        };
+        let _ = ctl_tx.send(ControlMessage::StartMeasure(measure, tx)).await;
+        let status = command(&ctl_tx, MachineCommand::EmergencyStop)
+            .await
+            .unwrap();
+        assert_eq!(status, MachineStatus::Error("emergency stop".into()));
+        assert!(matches!(
+            started.await.unwrap(),
+            Err(ControlError::Fault(_))
+        ));
+        assert_eq!(sim.position(), 0.0);
+    }
+
+    /// Configuration streaming the points only at the end of the drawing.
+    fn unstreamed_config() -> Configuration {
+        Configuration {
+            live_update_interval: 60.0,
+            ..config()
+        }
+    }
+
+    #[tokio::test]
+    async fn fault_stores_recorded_points() {
+        tokio::time::pause();
+        let config = unstreamed_config();
+        let (db, points, _) = mock_db(0.7);
+        let (_sim, ctl_tx, _shutdown_rx, handle) = spawn(&config, db);
+
+        start_measure(&ctl_tx).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        command(&ctl_tx, MachineCommand::EmergencyStop)
+            .await
+            .unwrap();
+        let _ = ctl_tx.send(ControlMessage::Terminate).await;
+        handle.await.unwrap();
+        let ticks = (0.1 / config.draw_measure_interval) as usize;
+        assert!(points.lock().unwrap().len() >= ticks);
+    }
+
+    #[tokio::test]
+    async fn terminate_stores_recorded_points() {
+        tokio::time::pause();
+        let config = unstreamed_config();
+        let (db, points, _) = mock_db(0.7);
+        let (sim, ctl_tx, _shutdown_rx, handle) = spawn(&config, db);
+
+        start_measure(&ctl_tx).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let _ = ctl_tx.send(ControlMessage::Terminate).await;
+        handle.await.unwrap();
+        assert!(sim.position() > 0.0);
+        let ticks = (0.1 / config.draw_measure_interval) as usize;
+        assert!(points.lock().unwrap().len() >= ticks);
     }
 }
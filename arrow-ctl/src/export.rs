@@ -0,0 +1,225 @@
+use super::models::*;
+use super::server::database::ArrowDB;
+use std::borrow::Cow;
+use std::fmt::Write;
+
+/// Data exported as CSV, values are in SI units as given in the header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportTarget {
+    /// The points of a measure.
+    Measure(i32),
+    /// All measures of a series with bow, arrow and results.
+    Series(i32),
+}
+
+impl ExportTarget {
+    pub fn file_name(&self) -> String {
+        match self {
+            ExportTarget::Measure(id) => format!("measure-{}.csv", id),
+            ExportTarget::Series(id) => format!("measure-series-{}.csv", id),
+        }
+    }
+}
+
+pub async fn export<D: ArrowDB>(db: &D, target: ExportTarget) -> Result<String, sqlx::Error> {
+    match target {
+        ExportTarget::Measure(id) => {
+            // unknown measures are not found, measures without points are empty
+            db.get_measure(id).await?;
+            Ok(measure_csv(&db.list_measure_points(id).await?))
+        }
+        ExportTarget::Series(id) => series_csv(db, id).await,
+    }
+}
+
+fn measure_csv(points: &[MeasurePoint]) -> String {
+    let mut csv = String::from("time [s],draw_distance [m],force [N]\n");
+    for p in points {
+        let _ = writeln!(
+            csv,
+            "{},{},{}",
+            p.time as f64 * 1e-6,
+            p.draw_distance,
+            p.force
+        );
+    }
+    csv
+}
+
+async fn series_csv<D: ArrowDB>(db: &D, id: i32) -> Result<String, sqlx::Error> {
+    let series = db.get_measure_series(id).await?;
+    let bow = db.get_bow(series.bow_id).await?;
+    let arrows = db.list_arrows(bow.id).await?;
+    let results = db.list_measure_results(id).await?;
+
+    let mut csv = String::from(
+        "series,bow,rest_position [m],draw_distance [m],draw_force [N],\
+        measure_id,measure_interval [s],arrow,arrow_length [m],arrow_weight [kg],\
+        arrow_speed [m/s],potential_energy [J],kinetic_energy [J],efficiency [-],wasted_energy [J]\n",
+    );
+    for measure in db.list_measures(id).await? {
+        let arrow = arrows.iter().find(|a| a.id == measure.arrow_id);
+        let _ = write!(
+            csv,
+            "{},{},{},{},{},{},{},",
+            field(&series.name),
+            field(&bow.name),
+            series.rest_position,
+            optional(series.draw_distance),
+            optional(series.draw_force),
+            measure.id,
+            measure.measure_interval,
+        );
+        match arrow {
+            Some(a) => {
+                let name = a.name.clone().unwrap_or_else(|| format!("#{}", a.id));
+                let _ = write!(csv, "{},{},{},", field(&name), a.length, a.weight);
+            }
+            None => csv.push_str(",,,"),
+        }
+        match results.iter().find(|r| r.measure_id == measure.id) {
+            Some(r) => {
+                let _ = writeln!(
+                    csv,
+                    "{},{},{},{},{}",
+                    r.arrow_speed,
+                    r.potential_energy,
+                    r.kinetic_energy,
+                    r.efficiency,
+                    r.wasted_energy
+                );
+            }
+            None => csv.push_str(",,,,\n"),
+        }
+    }
+    Ok(csv)
+}
+
+fn optional(value: Option<f32>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// Quotes text fields containing separators, quotes or line breaks.
+fn field(value: &str) -> Cow<'_, str> {
+    if value.contains(&[',', '"', '\n', '\r'][..]) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::database::InMemoryArrowDB;
+    use time::OffsetDateTime;
+
+    #[test]
+    fn fields_are_quoted() {
+        assert_eq!(field("bow"), "bow");
+        assert_eq!(field("30\", 40 lbs"), "\"30\"\", 40 lbs\"");
+    }
+
+    #[test]
+    fn points_are_exported_in_seconds() {
+        let point = |time, draw_distance, force| MeasurePoint {
+            id: -1,
+            time,
+            draw_distance,
+            force,
+            measure_id: 1,
+        };
+        assert_eq!(
+            measure_csv(&[point(0, 0.2, 0.0), point(1_500, 0.25, 12.5)]),
+            "time [s],draw_distance [m],force [N]\n0,0.2,0\n0.0015,0.25,12.5\n"
+        );
+        assert_eq!(measure_csv(&[]), "time [s],draw_distance [m],force [N]\n");
+    }
+
+    #[tokio::test]
+    async fn unknown_measure_is_not_found() {
+        let db = InMemoryArrowDB::new();
+        assert!(matches!(
+            export(&db, ExportTarget::Measure(1)).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn series_is_exported_with_results() {
+        let db = InMemoryArrowDB::new();
+        let bow = db
+            .add_bow(Bow {
+                id: -1,
+                name: "recurve, 30 lbs".into(),
+                max_draw_distance: 0.8,
+                remainder_arrow_length: 0.1,
+            })
+            .await
+            .unwrap();
+        let series = db
+            .add_measure_series(MeasureSeries {
+                id: -1,
+                name: "series".into(),
+                rest_position: 0.2,
+                draw_distance: Some(0.5),
+                draw_force: None,
+                time: OffsetDateTime::now_utc(),
+                bow_id: bow.id,
+            })
+            .await
+            .unwrap();
+        let arrow = db
+            .add_arrow(Arrow {
+                id: -1,
+                name: None,
+                head_weight: None,
+                spline: None,
+                feather_length: None,
+                feather_type: None,
+                length: 0.75,
+                weight: 0.025,
+                bow_id: bow.id,
+            })
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            db.add_measure(Measure {
+                id: -1,
+                measure_interval: 0.01,
+                measure_series_id: series.id,
+                arrow_id: arrow.id,
+            })
+            .await
+            .unwrap();
+        }
+        db.add_measure_result(MeasureResult {
+            id: -1,
+            measure_id: 1,
+            arrow_speed: 50.0,
+            potential_energy: 40.0,
+            kinetic_energy: 31.25,
+            efficiency: 0.78125,
+            wasted_energy: 8.75,
+        })
+        .await
+        .unwrap();
+
+        let csv = export(&db, ExportTarget::Series(series.id)).await.unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("series,bow,rest_position [m]"));
+        assert_eq!(
+            lines[1],
+            "series,\"recurve, 30 lbs\",0.2,0.5,,1,0.01,#1,0.75,0.025,50,40,31.25,0.78125,8.75"
+        );
+        assert_eq!(
+            lines[2],
+            "series,\"recurve, 30 lbs\",0.2,0.5,,2,0.01,#1,0.75,0.025,,,,,"
+        );
+        assert!(matches!(
+            export(&db, ExportTarget::Series(9)).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+}
@@ -1,19 +1,27 @@
+mod archive;
+mod calculation;
 mod config;
 mod controller;
+mod export;
 mod message;
 mod server;
 mod models;
+mod schema;
 mod serde_timestamp;
 
-use clap::{App, Arg};
-use config::{CmdArgs, Configuration};
+use clap::{value_t, values_t, App, Arg, ArgGroup, SubCommand};
+use config::{CmdArgs, Command, Configuration, DBBackend, HardwareBackend, TlsConfiguration};
+use export::ExportTarget;
+use models::{Role, User};
 use log::{debug, error, info, warn, trace};
 use log4rs;
 use serde_json;
 use std::env;
 use std::error::Error;
+use std::net::IpAddr;
 use std::thread;
-use tokio::runtime::Builder;
+use server::database::{ArrowDB, InMemoryArrowDB, PgArrowDB};
+use tokio::runtime::{Builder, Runtime};
 
 use signal_hook::consts::signal::*;
 use signal_hook_tokio::Signals;
@@ -49,6 +57,117 @@ fn parse_args() -> CmdArgs {
              .help("Sets the file log file path.")
              .default_value("/tmp/arrow.log")
              .takes_value(true))
+        .arg(Arg::with_name("simulate")
+             .short("s")
+             .long("simulate")
+             .help("Uses the simulated hardware backend.")
+             .long_help("Uses the simulated bow and machine instead of the hardware configured in the config file. Allows running without a Raspberry Pi attached."))
+        .arg(Arg::with_name("address")
+             .short("a")
+             .long("address")
+             .value_name("IP")
+             .help("Listens on the address instead of the configured ones.")
+             .long_help("Listens on the address instead of the configured ones. Can be used multiple times to listen on several addresses.")
+             .multiple(true)
+             .number_of_values(1)
+             .takes_value(true))
+        .arg(Arg::with_name("port")
+             .short("p")
+             .long("port")
+             .value_name("PORT")
+             .help("Sets the port of the webserver.")
+             .takes_value(true))
+        .arg(Arg::with_name("tls_cert")
+             .long("tls-cert")
+             .value_name("FILE")
+             .help("Serves https with the PEM encoded certificate chain.")
+             .requires("tls_key")
+             .takes_value(true))
+        .arg(Arg::with_name("tls_key")
+             .long("tls-key")
+             .value_name("FILE")
+             .help("Sets the PEM encoded private key of the certificate.")
+             .requires("tls_cert")
+             .takes_value(true))
+        .arg(Arg::with_name("static_dir")
+             .long("static-dir")
+             .value_name("DIR")
+             .help("Serves the web front-end from the directory.")
+             .takes_value(true))
+        .arg(Arg::with_name("migrate")
+             .long("migrate")
+             .help("Applies pending database migrations and exits.")
+             .long_help("Applies pending database migrations and exits. Migrations are also applied on every start, existing data is kept."))
+        .arg(Arg::with_name("pending_migrations")
+             .long("pending-migrations")
+             .help("Lists pending database migrations and exits.")
+             .conflicts_with("migrate"))
+        .subcommand(SubCommand::with_name("export")
+             .about("Exports recorded data as CSV and exits.")
+             .arg(Arg::with_name("measure")
+                  .long("measure")
+                  .value_name("ID")
+                  .help("Exports the points of a measure.")
+                  .takes_value(true))
+             .arg(Arg::with_name("series")
+                  .long("series")
+                  .value_name("ID")
+                  .help("Exports the measures of a series with bow, arrows and results.")
+                  .takes_value(true))
+             .group(ArgGroup::with_name("target")
+                  .args(&["measure", "series"])
+                  .required(true))
+             .arg(Arg::with_name("output")
+                  .short("o")
+                  .long("output")
+                  .value_name("FILE")
+                  .help("Writes the CSV to the file instead of stdout.")
+                  .takes_value(true)))
+        .subcommand(SubCommand::with_name("archive")
+             .about("Exports a bow with all its arrows and measures as JSON archive and exits.")
+             .arg(Arg::with_name("bow")
+                  .long("bow")
+                  .value_name("ID")
+                  .help("Id of the bow to export.")
+                  .required(true)
+                  .takes_value(true))
+             .arg(Arg::with_name("output")
+                  .short("o")
+                  .long("output")
+                  .value_name("FILE")
+                  .help("Writes the archive to the file instead of stdout.")
+                  .takes_value(true)))
+        .subcommand(SubCommand::with_name("import")
+             .about("Imports a bow from a JSON archive and exits.")
+             .long_about("Imports a bow from a JSON archive and exits. Bows and measure series with names already in use get a numbered suffix.")
+             .arg(Arg::with_name("file")
+                  .value_name("FILE")
+                  .help("Archive written by the archive subcommand.")
+                  .required(true)))
+        .subcommand(SubCommand::with_name("add-user")
+             .about("Adds a user account and exits, the password is read from stdin.")
+             .arg(Arg::with_name("name")
+                  .value_name("NAME")
+                  .help("Name used to log in.")
+                  .required(true))
+             .arg(Arg::with_name("role")
+                  .long("role")
+                  .value_name("ROLE")
+                  .help("Viewers can only read data, operators can also change it and control the machine.")
+                  .possible_values(&["viewer", "operator"])
+                  .default_value("viewer")
+                  .takes_value(true)))
+        .subcommand(SubCommand::with_name("schema")
+             .about("Writes the JSON Schema of the websocket protocol and exits.")
+             .arg(Arg::with_name("typescript")
+                  .long("typescript")
+                  .help("Writes TypeScript definitions instead of the JSON Schema."))
+             .arg(Arg::with_name("output")
+                  .short("o")
+                  .long("output")
+                  .value_name("FILE")
+                  .help("Writes the schema to the file instead of stdout.")
+                  .takes_value(true)))
         .get_matches();
 
     let verbosity = match matches.occurrences_of("verbosity") {
@@ -67,10 +186,67 @@ fn parse_args() -> CmdArgs {
     // can therefore never be None
     let log_file = matches.value_of("log_file").unwrap().to_string();
 
+    let simulate = matches.is_present("simulate");
+    let migrate = matches.is_present("migrate");
+    let pending_migrations = matches.is_present("pending_migrations");
+
+    let addresses = if matches.is_present("address") {
+        values_t!(matches, "address", IpAddr).unwrap_or_else(|e| e.exit())
+    } else {
+        Vec::new()
+    };
+    let port = if matches.is_present("port") {
+        Some(value_t!(matches, "port", u16).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    };
+    let tls = match (matches.value_of("tls_cert"), matches.value_of("tls_key")) {
+        (Some(cert), Some(key)) => Some(TlsConfiguration {
+            cert: cert.to_string(),
+            key: key.to_string(),
+        }),
+        _ => None,
+    };
+
+    let command = match matches.subcommand() {
+        ("export", Some(export)) => Some(Command::Export {
+            target: if export.is_present("measure") {
+                ExportTarget::Measure(value_t!(export, "measure", i32).unwrap_or_else(|e| e.exit()))
+            } else {
+                ExportTarget::Series(value_t!(export, "series", i32).unwrap_or_else(|e| e.exit()))
+            },
+            output: export.value_of("output").map(String::from),
+        }),
+        ("archive", Some(archive)) => Some(Command::Archive {
+            bow_id: value_t!(archive, "bow", i32).unwrap_or_else(|e| e.exit()),
+            output: archive.value_of("output").map(String::from),
+        }),
+        ("import", Some(import)) => Some(Command::Import {
+            file: import.value_of("file").unwrap().to_string(),
+        }),
+        ("add-user", Some(user)) => Some(Command::AddUser {
+            name: user.value_of("name").unwrap().to_string(),
+            role: value_t!(user, "role", Role).unwrap_or_else(|e| e.exit()),
+        }),
+        ("schema", Some(schema)) => Some(Command::Schema {
+            typescript: schema.is_present("typescript"),
+            output: schema.value_of("output").map(String::from),
+        }),
+        _ => None,
+    };
+
     CmdArgs {
         verbosity,
         config_file,
         log_file,
+        simulate,
+        migrate,
+        pending_migrations,
+        addresses,
+        port,
+        tls,
+        static_dir: matches.value_of("static_dir").map(String::from),
+        command,
     }
 }
 
@@ -155,23 +331,46 @@ fn configure_logging(
 fn main() -> Result<(), Box<dyn Error>> {
     let args = parse_args();
     configure_logging(args.verbosity, &args.log_file)?;
+    // needs neither configuration nor database
+    if let Some(Command::Schema { typescript, output }) = &args.command {
+        return write_schema(*typescript, output.clone());
+    }
 
     let conf_path = args.config_file.unwrap_or_else(|| {
         env::var("ARROW_CONFIG").unwrap_or_else(|_| "~/.config/arrow/config".to_string())
     });
 
     info!("Trying to read config file from '{}'.", conf_path);
-    let config: Configuration = match std::fs::File::open(conf_path) {
+    let mut config: Configuration = match std::fs::File::open(conf_path) {
         Ok(file) => {
             let reader = std::io::BufReader::new(file);
-            serde_json::from_reader(reader).unwrap_or_default()
+            serde_json::from_reader(reader).map_err(|e| {
+                error!("Cannot parse config file: '{}'.", e);
+                e
+            })?
         }
         Err(e) => {
             warn!("Cannot open config file: '{}'. Using default values.", e);
             Default::default()
         }
     };
+    if args.simulate {
+        config.hardware = HardwareBackend::Simulated;
+    }
+    if !args.addresses.is_empty() {
+        config.server.addresses = args.addresses;
+    }
+    if let Some(port) = args.port {
+        config.server.port = port;
+    }
+    if args.tls.is_some() {
+        config.server.tls = args.tls;
+    }
+    if args.static_dir.is_some() {
+        config.server.static_dir = args.static_dir;
+    }
     debug!("Using config {:#?}", config);
+    config.validate()?;
 
     let web_rt = Builder::new_multi_thread()
         .enable_io()
@@ -185,15 +384,138 @@ fn main() -> Result<(), Box<dyn Error>> {
         .thread_name("arrow-hw-tokio-worker")
         .build()?;
 
-    let (srv_handle, server) = web_rt.block_on(server::new(&config))?;
-    let mut ctl = controller::Controller::new(server.clone())?;
-    let ctl_sender = ctl.ctl_tx.clone();
-    let hw_thread = thread::spawn(move || {
-        hardware_rt.block_on(ctl.start());
-    });
+    match config.db.backend {
+        DBBackend::Postgres => {
+            let db = web_rt.block_on(PgArrowDB::connect(&config.db))?;
+            if args.pending_migrations {
+                for migration in web_rt.block_on(db.pending_migrations())? {
+                    println!("{} {}", migration.version, migration.description);
+                }
+                return Ok(());
+            }
+            web_rt.block_on(db.migrate())?;
+            if args.migrate {
+                return Ok(());
+            }
+            start(&config, args.command, db, web_rt, hardware_rt)
+        }
+        _ if args.migrate || args.pending_migrations => {
+            info!("No migrations for the {:?} database backend.", config.db.backend);
+            Ok(())
+        }
+        DBBackend::Memory if config.server.authentication => {
+            Err("authentication needs stored accounts, use a persistent database backend or disable authentication.".into())
+        }
+        DBBackend::Memory => {
+            warn!("Using in-memory database, all data is lost on shutdown.");
+            start(&config, args.command, InMemoryArrowDB::new(), web_rt, hardware_rt)
+        }
+        #[cfg(feature = "sqlite")]
+        DBBackend::Sqlite => {
+            let db = web_rt.block_on(server::database::SqliteArrowDB::connect(&config.db))?;
+            start(&config, args.command, db, web_rt, hardware_rt)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        DBBackend::Sqlite => {
+            Err("sqlite backend not available, rebuild with '--features sqlite'.".into())
+        }
+    }
+}
+
+fn start<D: ArrowDB + 'static>(
+    config: &Configuration,
+    command: Option<Command>,
+    db: D,
+    web_rt: Runtime,
+    hardware_rt: Runtime,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        Some(Command::Export { target, output }) => {
+            let csv = web_rt.block_on(export::export(&db, target))?;
+            write_output(output, &csv)
+        }
+        Some(Command::Archive { bow_id, output }) => {
+            let archive = web_rt.block_on(archive::export_bow(&db, bow_id))?;
+            write_output(output, &serde_json::to_string_pretty(&archive)?)
+        }
+        Some(Command::Import { file }) => {
+            let reader = std::io::BufReader::new(std::fs::File::open(file)?);
+            let archive = serde_json::from_reader(reader)?;
+            let bow = web_rt.block_on(archive::import_bow(&db, archive))?;
+            println!("Imported bow '{}' with id {}.", bow.name, bow.id);
+            Ok(())
+        }
+        Some(Command::AddUser { name, role }) => {
+            let mut password = String::new();
+            std::io::stdin().read_line(&mut password)?;
+            let password = password.trim_end_matches(&['\r', '\n'][..]);
+            if password.is_empty() {
+                return Err("no password given on stdin.".into());
+            }
+            let user = User {
+                id: -1,
+                name,
+                password_hash: server::auth::hash_password(password),
+                role,
+            };
+            let user = web_rt.block_on(db.add_user(user))?;
+            println!("Added {} '{}'.", user.role.as_str(), user.name);
+            Ok(())
+        }
+        Some(Command::Schema { typescript, output }) => write_schema(typescript, output),
+        None => run(config, db, web_rt, hardware_rt),
+    }
+}
+
+fn write_schema(typescript: bool, output: Option<String>) -> Result<(), Box<dyn Error>> {
+    let protocol = schema::protocol();
+    let content = if typescript {
+        schema::typescript(&protocol)
+    } else {
+        serde_json::to_string_pretty(&protocol)? + "\n"
+    };
+    write_output(output, &content)
+}
+
+fn write_output(output: Option<String>, content: &str) -> Result<(), Box<dyn Error>> {
+    match output {
+        Some(path) => std::fs::write(path, content)?,
+        None => print!("{}", content),
+    }
+    Ok(())
+}
+
+fn run<D: ArrowDB + 'static>(
+    config: &Configuration,
+    db: D,
+    web_rt: Runtime,
+    hardware_rt: Runtime,
+) -> Result<(), Box<dyn Error>> {
+    let (ctl_sender, ctl_receiver) = message::control_channel(controller::CONTROL_CHANNEL_SIZE);
+    let (srv_handle, server) = web_rt.block_on(async { server::new(db, ctl_sender, &config.server) });
+    // without hardware the recorded data is still served, machine requests
+    // fail as the controller is unavailable
+    let hw_thread = match controller::create_hardware(config) {
+        Ok(hardware) => {
+            // the controller spawns its background tasks on the hardware runtime
+            let mut ctl = {
+                let _rt = hardware_rt.enter();
+                controller::Controller::new(server.clone(), hardware, config, ctl_receiver)?
+            };
+            Some(thread::spawn(move || {
+                hardware_rt.block_on(ctl.start());
+            }))
+        }
+        Err(e) => {
+            error!(target: "arrow::hw", "cannot start the machine controller: '{}'", e);
+            drop(ctl_receiver);
+            None
+        }
+    };
 
     //TODO: gracefully shut down hardware
-    let _ = web_rt.block_on(async move {
+    let control_tx = server.control_tx.clone();
+    let result = web_rt.block_on(async move {
         let signals = Signals::new(&[SIGHUP, SIGTERM, SIGINT, SIGQUIT])?;
         let handle = signals.handle();
 
@@ -207,8 +529,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                     }
                     SIGTERM | SIGINT | SIGQUIT => {
                         println!("\rReceived shutdown Signal. Shutting down.");
-                        let _ = ctl_sender.send(message::ControlMessage::Terminate).await;
-                        if let Err(e) = server.shutdown_tx.send(()).await {
+                        if let Err(e) = server.shutdown().await {
                             error!("Error while shutting down: '{}'. Aborting.", e);
                             panic!("Aborted due to error while shutting down.");
                         }
@@ -217,14 +538,21 @@ fn main() -> Result<(), Box<dyn Error>> {
                 }
             }
         });
-        srv_handle.bind(([127, 0, 0, 1], 6000))?.1.await;
+        srv_handle.bind(&config.server.socket_addrs(), config.server.tls.as_ref())?.await;
 
         handle.close();
         // Rust type inference helper
         Ok::<(), Box<dyn Error>>(())
     });
+    if let Err(e) = &result {
+        error!("webserver stopped: '{}'", e);
+        // the controller only stops on request
+        let _ = web_rt.block_on(control_tx.send(message::ControlMessage::Terminate));
+    }
 
-    trace!("waiting to join hardware thread.");
-    hw_thread.join().unwrap();
-    Ok(())
+    if let Some(hw_thread) = hw_thread {
+        trace!("waiting to join hardware thread.");
+        hw_thread.join().unwrap();
+    }
+    result
 }
@@ -1,62 +1,387 @@
+use super::controller::ControlError;
 use super::models::*;
 use log::error;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::convert::TryFrom;
 use std::result::Result;
+use tokio::sync::{mpsc, oneshot};
+
+/// Version of the websocket protocol, raised on incompatible changes.
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Version spoken by clients which do not send a `Hello`.
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+/// Oldest client protocol version the server still accepts, once it is above
+/// `LEGACY_PROTOCOL_VERSION` clients have to start with a `Hello`.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+pub type ControlReply<T> = oneshot::Sender<Result<T, ControlError>>;
+
+/// Sending side of the controller channels. Emergency stops get a channel of
+/// their own, so they are not queued behind other requests.
+#[derive(Clone, Debug)]
+pub struct ControlSender {
+    control: mpsc::Sender<ControlMessage>,
+    emergency: mpsc::Sender<ControlReply<MachineStatus>>,
+}
+
+impl ControlSender {
+    pub async fn send(
+        &self,
+        message: ControlMessage,
+    ) -> Result<(), mpsc::error::SendError<ControlMessage>> {
+        match message {
+            ControlMessage::Command(MachineCommand::EmergencyStop, reply) => {
+                self.emergency.send(reply).await.map_err(|e| {
+                    mpsc::error::SendError(ControlMessage::Command(
+                        MachineCommand::EmergencyStop,
+                        e.0,
+                    ))
+                })
+            }
+            message => self.control.send(message).await,
+        }
+    }
+}
+
+pub struct ControlReceiver {
+    pub control: mpsc::Receiver<ControlMessage>,
+    pub emergency: mpsc::Receiver<ControlReply<MachineStatus>>,
+}
+
+/// Channels to the controller, `size` requests may be queued.
+pub fn control_channel(size: usize) -> (ControlSender, ControlReceiver) {
+    let (control, control_rx) = mpsc::channel(size);
+    let (emergency, emergency_rx) = mpsc::channel(size);
+    (
+        ControlSender { control, emergency },
+        ControlReceiver {
+            control: control_rx,
+            emergency: emergency_rx,
+        },
+    )
+}
 
-#[derive(Clone)]
 pub enum ControlMessage {
+    StartMeasure(Measure, ControlReply<Measure>),
+    Command(MachineCommand, ControlReply<MachineStatus>),
     Terminate,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct WSRegisterResponse {
     pub url: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum WSUpdate {
     Alive {},
+    /// A single bow, the reply to `GetBow`.
+    Bow(Bow),
     BowList(Vec<Bow>),
     MeasureSeriesList(Vec<MeasureSeries>),
     ArrowList(Vec<Arrow>),
     MeasureList(Vec<Measure>),
     MeasurePointList(Vec<MeasurePoint>),
+    LiveMeasurePoints {
+        measure_id: i32,
+        points: Vec<MeasurePoint>,
+    },
+    MeasureResultList(Vec<MeasureResult>),
     Status(MachineStatus),
+    /// Connection state of the backend services.
+    Health {
+        database: ConnectionState,
+    },
+    /// Updates were lost, e.g. while the database was unreachable, clients
+    /// should reload their lists.
+    Resync {},
+    Subscriptions(Vec<Topic>),
+    /// Rows deleted from a table, including rows removed by cascade.
+    Removed {
+        table: String,
+        ids: Vec<i32>,
+    },
     Error(String),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Part of the data a client can subscribe to.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Topic {
+    /// Bows being added or changed.
+    Bows,
+    /// A bow with its series and arrows.
+    Bow(i32),
+    /// A measure series with its measures.
+    Series(i32),
+    /// A measure with its points and result.
+    Measure(i32),
+    /// Machine status and health changes.
+    Status,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum WSRequest {
     ListBows {},
+    GetBow { id: i32 },
     ListMeasureSeries { bow_id: i32 },
     ListArrows { bow_id: i32 },
     ListMeasures { series_id: i32 },
     ListMeasurePoints { measure_id: i32 },
+    ListMeasureResults { series_id: i32 },
     AddBow(Bow),
+    UpdateBow(Bow),
+    DeleteBow { id: i32 },
     AddArrow(Arrow),
+    UpdateArrow(Arrow),
+    DeleteArrow { id: i32 },
     NewMeasureSeries(MeasureSeries),
+    RenameMeasureSeries { id: i32, name: String },
+    DeleteMeasureSeries { id: i32 },
+    DeleteMeasure { id: i32 },
     StartMeasure(Measure),
     Command(MachineCommand),
+    GetHealth {},
+    Subscribe(Vec<Topic>),
+    Unsubscribe(Vec<Topic>),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum MachineCommand {
     Calibrate,
     Reset,
     Restart,
     Shutdown,
+    EmergencyStop,
+    ClearError,
+}
+
+/// Category of a failed request, so clients do not need to parse the message.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorCode {
+    BadMessage,
+    BadRequest,
+    NotFound,
+    Database,
+    Busy,
+    Fault,
+    Hardware,
+    Unavailable,
+    /// The request needs a login.
+    Unauthorized,
+    /// The user's role does not allow the request.
+    Forbidden,
+    /// The client's protocol version is not supported, the connection is
+    /// closed.
+    Incompatible,
+}
+
+/// Optional parts of the protocol, clients announce the ones they understand
+/// in their `Hello` and only get unsolicited updates of those.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Feature {
+    /// `Subscriptions` updates, e.g. when a session is resumed.
+    Subscriptions,
+    /// `Removed` updates for deleted rows.
+    Removals,
+    /// `Health` and `Resync` updates on database connection changes.
+    Health,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Requests may carry an id chosen by the client, which is echoed in the
+/// matching `Response` or `Error`. Clients may start with a `Hello`, which the
+/// server answers with its version and the features both sides support.
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum WSMessage {
-    Request(WSRequest),
+    Request {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        #[serde(flatten)]
+        request: WSRequest,
+    },
     Update(WSUpdate),
-    Response(WSUpdate),
+    Response {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        #[serde(flatten)]
+        update: WSUpdate,
+    },
+    Error {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        code: ErrorCode,
+        message: String,
+    },
+    Hello {
+        version: u32,
+        /// Names of `Feature`s, unknown ones are ignored.
+        #[serde(default)]
+        features: Vec<String>,
+    },
+}
+
+impl WSUpdate {
+    /// Protocol version which introduced the update, clients speaking an
+    /// older version never get it unasked.
+    pub fn since(&self) -> u32 {
+        match self {
+            WSUpdate::Alive {}
+            | WSUpdate::BowList(_)
+            | WSUpdate::MeasureSeriesList(_)
+            | WSUpdate::ArrowList(_)
+            | WSUpdate::MeasureList(_)
+            | WSUpdate::MeasurePointList(_)
+            | WSUpdate::Status(_)
+            | WSUpdate::Error(_) => 1,
+            WSUpdate::Bow(_)
+            | WSUpdate::LiveMeasurePoints { .. }
+            | WSUpdate::MeasureResultList(_)
+            | WSUpdate::Health { .. }
+            | WSUpdate::Resync {}
+            | WSUpdate::Subscriptions(_)
+            | WSUpdate::Removed { .. } => 2,
+        }
+    }
+
+    /// Topics the update belongs to, each listed once. Updates without topics
+    /// are sent to all clients.
+    pub fn topics(&self) -> Vec<Topic> {
+        let mut topics = match self {
+            WSUpdate::Bow(bow) => vec![Topic::Bows, Topic::Bow(bow.id)],
+            WSUpdate::BowList(bows) => std::iter::once(Topic::Bows)
+                .chain(bows.iter().map(|b| Topic::Bow(b.id)))
+                .collect(),
+            WSUpdate::MeasureSeriesList(series) => series
+                .iter()
+                .flat_map(|s| vec![Topic::Bow(s.bow_id), Topic::Series(s.id)])
+                .collect(),
+            WSUpdate::ArrowList(arrows) => arrows.iter().map(|a| Topic::Bow(a.bow_id)).collect(),
+            WSUpdate::MeasureList(measures) => measures
+                .iter()
+                .flat_map(|m| vec![Topic::Series(m.measure_series_id), Topic::Measure(m.id)])
+                .collect(),
+            WSUpdate::MeasurePointList(points) => points
+                .iter()
+                .map(|p| Topic::Measure(p.measure_id))
+                .collect(),
+            WSUpdate::LiveMeasurePoints { measure_id, .. } => vec![Topic::Measure(*measure_id)],
+            WSUpdate::MeasureResultList(results) => results
+                .iter()
+                .map(|r| Topic::Measure(r.measure_id))
+                .collect(),
+            WSUpdate::Status(_) | WSUpdate::Health { .. } => vec![Topic::Status],
+            WSUpdate::Removed { table, ids } => match table.as_str() {
+                "bow" => std::iter::once(Topic::Bows)
+                    .chain(ids.iter().map(|id| Topic::Bow(*id)))
+                    .collect(),
+                "measure_series" => ids.iter().map(|id| Topic::Series(*id)).collect(),
+                "measure" => ids.iter().map(|id| Topic::Measure(*id)).collect(),
+                _ => Vec::new(),
+            },
+            WSUpdate::Alive {}
+            | WSUpdate::Resync {}
+            | WSUpdate::Subscriptions(_)
+            | WSUpdate::Error(_) => Vec::new(),
+        };
+        let mut seen = HashSet::new();
+        topics.retain(|topic| seen.insert(*topic));
+        topics
+    }
+}
+
+impl WSMessage {
+    /// Error reply in the shape the protocol version knows, version 1 only
+    /// has `Error` updates sent as `Response`.
+    pub fn error(version: u32, id: Option<String>, code: ErrorCode, message: String) -> Self {
+        if version <= LEGACY_PROTOCOL_VERSION {
+            WSMessage::Response {
+                id,
+                update: WSUpdate::Error(message),
+            }
+        } else {
+            WSMessage::Error { id, code, message }
+        }
+    }
+}
+
+impl Feature {
+    pub const ALL: [Feature; 3] = [Feature::Subscriptions, Feature::Removals, Feature::Health];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Feature::Subscriptions => "subscriptions",
+            Feature::Removals => "removals",
+            Feature::Health => "health",
+        }
+    }
+
+    /// Protocol version which introduced the feature, clients speaking an
+    /// older version never get it.
+    pub fn since(&self) -> u32 {
+        match self {
+            Feature::Subscriptions | Feature::Removals | Feature::Health => 2,
+        }
+    }
+
+    /// Feature a client must support to get the update unasked.
+    pub fn of(update: &WSUpdate) -> Option<Feature> {
+        match update {
+            WSUpdate::Subscriptions(_) => Some(Feature::Subscriptions),
+            WSUpdate::Removed { .. } => Some(Feature::Removals),
+            WSUpdate::Health { .. } | WSUpdate::Resync {} => Some(Feature::Health),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for Feature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Feature::ALL
+            .iter()
+            .find(|f| f.as_str() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown feature '{}'", s))
+    }
+}
+
+impl WSRequest {
+    /// Role needed to execute the request, viewers may only read.
+    pub fn role(&self) -> Role {
+        match self {
+            WSRequest::ListBows {}
+            | WSRequest::GetBow { .. }
+            | WSRequest::ListMeasureSeries { .. }
+            | WSRequest::ListArrows { .. }
+            | WSRequest::ListMeasures { .. }
+            | WSRequest::ListMeasurePoints { .. }
+            | WSRequest::ListMeasureResults { .. }
+            | WSRequest::GetHealth {}
+            | WSRequest::Subscribe(_)
+            | WSRequest::Unsubscribe(_) => Role::Viewer,
+            WSRequest::AddBow(_)
+            | WSRequest::UpdateBow(_)
+            | WSRequest::DeleteBow { .. }
+            | WSRequest::AddArrow(_)
+            | WSRequest::UpdateArrow(_)
+            | WSRequest::DeleteArrow { .. }
+            | WSRequest::NewMeasureSeries(_)
+            | WSRequest::RenameMeasureSeries { .. }
+            | WSRequest::DeleteMeasureSeries { .. }
+            | WSRequest::DeleteMeasure { .. }
+            | WSRequest::StartMeasure(_)
+            | WSRequest::Command(_) => Role::Operator,
+        }
+    }
 }
 
 impl Into<warp::ws::Message> for WSMessage {
@@ -77,3 +402,25 @@ impl TryFrom<warp::ws::Message> for WSMessage {
         serde_json::de::from_str(&str)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topics_are_listed_once() {
+        let points = (0..100)
+            .map(|i| MeasurePoint {
+                id: i,
+                time: i as i64,
+                draw_distance: 0.0,
+                force: 0.0,
+                measure_id: 7,
+            })
+            .collect();
+        assert_eq!(
+            WSUpdate::MeasurePointList(points).topics(),
+            [Topic::Measure(7)]
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use crate::serde_timestamp;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct Bow {
     #[serde(default = "invalid_id")]
     pub id: i32,
@@ -11,7 +12,7 @@ pub struct Bow {
     pub remainder_arrow_length: f32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct MeasureSeries {
     #[serde(default = "invalid_id")]
     pub id: i32,
@@ -22,11 +23,12 @@ pub struct MeasureSeries {
     #[serde(default)]
     pub draw_force: Option<f32>,
     #[serde(with = "serde_timestamp")]
+    #[schemars(with = "String")]
     pub time: OffsetDateTime,
     pub bow_id: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct Arrow {
     #[serde(default = "invalid_id")]
     pub id: i32,
@@ -41,11 +43,12 @@ pub struct Arrow {
     #[serde(default)]
     pub feather_type: Option<String>,
     pub length: f32,
+    /// Arrow mass in kilogram.
     pub weight: f32,
     pub bow_id: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct Measure {
     #[serde(default = "invalid_id")]
     pub id: i32,
@@ -54,7 +57,7 @@ pub struct Measure {
     pub arrow_id: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct MeasurePoint {
     #[serde(default = "invalid_id")]
     pub id: i32,
@@ -64,12 +67,78 @@ pub struct MeasurePoint {
     pub measure_id: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Energy balance of a single shot, all values in SI units.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct MeasureResult {
+    #[serde(default = "invalid_id")]
+    pub id: i32,
+    pub measure_id: i32,
+    pub arrow_speed: f64,
+    pub potential_energy: f64,
+    pub kinetic_energy: f64,
+    pub efficiency: f64,
+    pub wasted_energy: f64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum MachineStatus {
     Pause,
+    Drawing,
+    Holding,
     Shooting,
-    Error,
+    Retracting,
+    Error(String),
+}
+
+/// State of the connection to the database server.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionState {
+    Connected,
+    Disconnected,
+}
+
+/// Permissions of a user, viewers can read data but only operators may
+/// change it or control the machine.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Operator,
+}
+
+impl Role {
+    /// Name as stored in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "operator" => Ok(Role::Operator),
+            _ => Err(format!("unknown role '{}'", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    #[serde(default = "invalid_id")]
+    pub id: i32,
+    pub name: String,
+    /// PBKDF2 hash of the password, never sent to clients.
+    #[serde(skip_serializing, default)]
+    pub password_hash: String,
+    pub role: Role,
 }
 
 fn invalid_id() -> i32 {
@@ -0,0 +1,257 @@
+use super::message::{Feature, WSMessage, WSRegisterResponse, WSRequest};
+use super::server::auth::{Credentials, LoginResponse};
+use schemars::gen::SchemaSettings;
+use serde_json::{Map, Value};
+use std::fmt::Write;
+
+/// JSON Schema of the messages exchanged over the websocket. The request and
+/// reply bodies of the REST endpoints are included as definitions.
+pub fn protocol() -> Value {
+    let mut generator = SchemaSettings::draft07().into_generator();
+    generator.subschema_for::<WSRequest>();
+    generator.subschema_for::<Feature>();
+    generator.subschema_for::<WSRegisterResponse>();
+    generator.subschema_for::<Credentials>();
+    generator.subschema_for::<LoginResponse>();
+    let schema = generator.into_root_schema_for::<WSMessage>();
+    serde_json::to_value(schema).expect("schema is valid JSON")
+}
+
+/// TypeScript declarations of the root schema and all its definitions.
+pub fn typescript(schema: &Value) -> String {
+    let mut ts = String::from("// Generated by `arrow schema --typescript`, do not edit.\n");
+    let root = schema
+        .get("title")
+        .and_then(Value::as_str)
+        .unwrap_or("Protocol");
+    declare(&mut ts, root, schema);
+    if let Some(Value::Object(definitions)) = schema.get("definitions") {
+        for (name, definition) in definitions {
+            declare(&mut ts, name, definition);
+        }
+    }
+    ts
+}
+
+fn declare(ts: &mut String, name: &str, schema: &Value) {
+    ts.push('\n');
+    doc(ts, schema, "");
+    let _ = writeln!(ts, "export type {} = {};", name, type_of(schema, ""));
+}
+
+fn doc(ts: &mut String, schema: &Value, indent: &str) {
+    let description = match schema.get("description").and_then(Value::as_str) {
+        Some(d) => d,
+        None => return,
+    };
+    if description.contains('\n') {
+        let _ = writeln!(ts, "{}/**", indent);
+        for line in description.lines() {
+            let _ = writeln!(ts, "{} * {}", indent, line);
+        }
+        let _ = writeln!(ts, "{} */", indent);
+    } else {
+        let _ = writeln!(ts, "{}/** {} */", indent, description);
+    }
+}
+
+fn type_of(schema: &Value, indent: &str) -> String {
+    let schema = match schema {
+        Value::Object(schema) => schema,
+        Value::Bool(false) => return "never".into(),
+        _ => return "unknown".into(),
+    };
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        return reference.rsplit('/').next().unwrap_or(reference).into();
+    }
+    if let Some(Value::Array(values)) = schema.get("enum") {
+        return union(values.iter().map(Value::to_string));
+    }
+
+    let mut parts = Vec::new();
+    match schema.get("type") {
+        Some(Value::String(name)) => parts.push(primitive(name, schema, indent)),
+        Some(Value::Array(names)) => parts.push(union(
+            names
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|name| primitive(name, schema, indent)),
+        )),
+        _ if schema.contains_key("properties") => parts.push(object(schema, indent)),
+        _ => {}
+    }
+    for key in &["oneOf", "anyOf"] {
+        if let Some(Value::Array(variants)) = schema.get(*key) {
+            parts.push(union(variants.iter().map(|v| type_of(v, indent))));
+        }
+    }
+    if let Some(Value::Array(all)) = schema.get("allOf") {
+        parts.extend(all.iter().map(|s| type_of(s, indent)));
+    }
+    match parts.len() {
+        0 => "unknown".into(),
+        1 => parts.pop().unwrap(),
+        _ => parts
+            .iter()
+            .map(|p| parenthesize(p))
+            .collect::<Vec<_>>()
+            .join(" & "),
+    }
+}
+
+fn primitive(name: &str, schema: &Map<String, Value>, indent: &str) -> String {
+    match name {
+        "string" => "string".into(),
+        "integer" | "number" => "number".into(),
+        "boolean" => "boolean".into(),
+        "null" => "null".into(),
+        "array" => match schema.get("items") {
+            Some(Value::Array(items)) => format!(
+                "[{}]",
+                items
+                    .iter()
+                    .map(|i| type_of(i, indent))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Some(items) => format!("{}[]", parenthesize(&type_of(items, indent))),
+            None => "unknown[]".into(),
+        },
+        "object" => object(schema, indent),
+        _ => "unknown".into(),
+    }
+}
+
+fn object(schema: &Map<String, Value>, indent: &str) -> String {
+    let properties = match schema.get("properties") {
+        Some(Value::Object(properties)) if !properties.is_empty() => properties,
+        _ => {
+            return match schema.get("additionalProperties") {
+                Some(values @ Value::Object(_)) => {
+                    format!("{{ [key: string]: {} }}", type_of(values, indent))
+                }
+                _ => "{}".into(),
+            }
+        }
+    };
+    let required: Vec<&str> = match schema.get("required") {
+        Some(Value::Array(required)) => required.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    let inner = format!("{}    ", indent);
+    let mut ts = String::from("{\n");
+    for (name, property) in properties {
+        doc(&mut ts, property, &inner);
+        let optional = if required.contains(&name.as_str()) {
+            ""
+        } else {
+            "?"
+        };
+        let _ = writeln!(
+            ts,
+            "{}{}{}: {};",
+            inner,
+            key(name),
+            optional,
+            type_of(property, &inner)
+        );
+    }
+    ts.push_str(indent);
+    ts.push('}');
+    ts
+}
+
+fn key(name: &str) -> String {
+    let identifier = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with(|c: char| c.is_ascii_digit());
+    if identifier && !name.is_empty() {
+        name.into()
+    } else {
+        Value::from(name).to_string()
+    }
+}
+
+fn union(types: impl Iterator<Item = String>) -> String {
+    let mut unique: Vec<String> = Vec::new();
+    for t in types {
+        if !unique.contains(&t) {
+            unique.push(t);
+        }
+    }
+    unique.join(" | ")
+}
+
+/// Wraps unions and intersections, so they can be combined with other types.
+fn parenthesize(ts: &str) -> String {
+    let mut depth = 0;
+    for c in ts.chars() {
+        match c {
+            '{' | '[' | '(' => depth += 1,
+            '}' | ']' | ')' => depth -= 1,
+            '|' | '&' if depth == 0 => return format!("({})", ts),
+            _ => {}
+        }
+    }
+    ts.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn enums_become_unions() {
+        let schema = json!({
+            "title": "Command",
+            "description": "Machine command.",
+            "oneOf": [
+                {"type": "string", "enum": ["reset", "shutdown"]},
+                {
+                    "type": "object",
+                    "required": ["status"],
+                    "properties": {"status": {"type": ["number", "null"]}},
+                    "additionalProperties": false
+                }
+            ]
+        });
+        assert_eq!(
+            typescript(&schema),
+            "// Generated by `arrow schema --typescript`, do not edit.\n\n\
+             /** Machine command. */\n\
+             export type Command = \"reset\" | \"shutdown\" | {\n    status: number | null;\n};\n"
+        );
+    }
+
+    #[test]
+    fn flattened_requests_are_intersected() {
+        let request = json!({
+            "type": "object",
+            "properties": {"id": {"type": ["string", "null"]}},
+            "oneOf": [
+                {"type": "object", "required": ["listbows"], "properties": {"listbows": {"type": "object"}}},
+                {"type": "object", "required": ["getbow"], "properties": {"getbow": {"$ref": "#/definitions/Id"}}}
+            ]
+        });
+        assert_eq!(
+            type_of(&request, ""),
+            "{\n    id?: string | null;\n} & ({\n    listbows: {};\n} | {\n    getbow: Id;\n})"
+        );
+        let list = json!({"type": "array", "items": {"anyOf": [{"$ref": "#/definitions/Bow"}, {"type": "null"}]}});
+        assert_eq!(type_of(&list, ""), "(Bow | null)[]");
+        assert_eq!(key("bow_id"), "bow_id");
+        assert_eq!(key("content-type"), "\"content-type\"");
+    }
+
+    #[test]
+    fn protocol_is_described() {
+        let schema = protocol();
+        let definitions = schema["definitions"].as_object().unwrap();
+        for name in &["WSRequest", "WSUpdate", "Bow", "LoginResponse"] {
+            assert!(definitions.contains_key(*name), "{} is missing", name);
+        }
+        let ts = typescript(&schema);
+        assert!(ts.contains("export type WSMessage = "));
+        assert!(ts.contains("export type MeasureSeries = {"));
+    }
+}
@@ -1,20 +1,31 @@
+pub mod auth;
 pub mod handler;
 //pub mod notification;
 pub mod database;
+pub mod frontend;
+pub mod rest;
 
-use super::config::Configuration;
-use super::message::WSUpdate;
-use log::{error, trace};
-use sqlx::PgPool;
+use super::config::{ServerConfiguration, TlsConfiguration};
+use super::export::ExportTarget;
+use super::message::{ControlMessage, ControlSender, WSUpdate};
+use super::models::ConnectionState;
+use futures::future::{Future, FutureExt};
+use log::{error, info, trace, warn};
 use std::collections::HashMap;
 use std::error::Error;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::time::Duration;
 use tokio::sync::RwLock;
+use tokio::sync::{broadcast, mpsc};
 use warp::Filter;
 
 const SHUTDOWN_CHANNEL_SIZE: usize = 8;
+/// Interval in which expired client registrations are removed.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+/// Largest archive accepted for import, in bytes.
+const ARCHIVE_SIZE_LIMIT: u64 = 64 * 1024 * 1024;
 
 #[derive(Clone, Debug)]
 pub struct Webserver<F>
@@ -22,25 +33,52 @@ where
     F: database::ArrowDB + Clone + Send,
 {
     pub shutdown_tx: mpsc::Sender<()>,
+    pub control_tx: ControlSender,
     sockets: handler::Clients,
     db: F,
+    /// Connection state of the database as last reported by the listener.
+    database_state: Arc<RwLock<ConnectionState>>,
+    sessions: auth::Sessions,
+    /// Clients must log in if set, otherwise everyone is an operator.
+    authentication: bool,
+    /// Directory of the web front-end, served if set.
+    frontend: Option<PathBuf>,
 }
 
 impl<F: database::ArrowDB + Clone + Send + 'static> Webserver<F> {
-    pub fn new(shutdown_tx: mpsc::Sender<()>, db: F) -> Self {
+    pub fn new(shutdown_tx: mpsc::Sender<()>, control_tx: ControlSender, db: F) -> Self {
         Self {
             shutdown_tx,
+            control_tx,
             sockets: Arc::new(RwLock::new(HashMap::new())),
             db,
+            database_state: Arc::new(RwLock::new(ConnectionState::Connected)),
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            authentication: false,
+            frontend: None,
         }
     }
 
+    pub fn db(&self) -> &F {
+        &self.db
+    }
+
+    /// Stops the controller and the webserver, same as receiving SIGTERM.
+    pub async fn shutdown(&self) -> Result<(), mpsc::error::SendError<()>> {
+        let _ = self.control_tx.send(ControlMessage::Terminate).await;
+        self.shutdown_tx.send(()).await
+    }
+
+    /// Sends the update to all clients subscribed to one of its topics which
+    /// support it.
     pub async fn broadcast(&self, msg: WSUpdate) {
         trace!("broadcasting: {:#?}", msg);
+        let topics = msg.topics();
         self.sockets
             .read()
             .await
             .iter()
+            .filter(|(_, client)| client.is_subscribed(&topics) && client.supports(&msg))
             .filter_map(|(_, client)| client.sender.as_ref())
             .for_each(|sender| {
                 let _ = sender.send(Ok(warp::ws::Message::text(
@@ -52,24 +90,63 @@ impl<F: database::ArrowDB + Clone + Send + 'static> Webserver<F> {
             });
     }
 
+    /// Removes registrations which were never connected or not resumed in
+    /// time and sessions which ran out.
+    pub async fn expire_clients(&self) {
+        let now = tokio::time::Instant::now();
+        self.sockets.write().await.retain(|id, client| {
+            let expired = matches!(client.expires, Some(expires) if expires <= now);
+            if expired {
+                info!(target: "arrow::web::ws", "{} expired", id);
+            }
+            !expired
+        });
+        let mut ended = Vec::new();
+        self.sessions.write().await.retain(|token, session| {
+            let expired = session.expires <= now;
+            if expired {
+                ended.push(token.clone());
+            }
+            !expired
+        });
+        handler::close_sessions(self, &ended).await;
+    }
+
+    fn sweep(self) {
+        tokio::spawn(async move {
+            let mut int = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                int.tick().await;
+                self.expire_clients().await;
+            }
+        });
+    }
+
     pub fn listen(self) {
         let srv = self.clone();
         let (tx, mut rx) = mpsc::unbounded_channel();
         tokio::spawn(async move {
             while let Some(msg) = rx.recv().await {
+                if let WSUpdate::Health { database } = msg {
+                    *self.database_state.write().await = database;
+                }
                 self.broadcast(msg).await;
             }
         });
-        tokio::spawn(async move { srv.db.listener(tx).await });
+        tokio::spawn(async move {
+            if let Err(e) = srv.db.listener(tx).await {
+                error!(target: "arrow::db", "database listener stopped: '{}'", e);
+            }
+        });
     }
 }
 
 pub struct Builder<F>
 where
-    F: Filter + Clone + Send + Sync + 'static,
+    F: Filter<Error = warp::Rejection> + Clone + Send + Sync + 'static,
     F::Extract: warp::Reply,
 {
-    server: warp::Server<F>,
+    routes: F,
     shutdown_rx: mpsc::Receiver<()>,
 }
 
@@ -78,18 +155,61 @@ fn register_routes<F: database::ArrowDB + 'static>(
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     //let base = warp::any().map(move || db.clone());
     //let api = base.and(warp::path("api"));
-    let routes = warp::path!("api" / "client" / "new")
+    let routes = warp::path!("api" / "login")
+        .and(warp::post())
         .and(with_db(db.clone()))
-        .and_then(handler::new_client)
+        .and(warp::body::json())
+        .and_then(auth::login)
+        .or(warp::path!("api" / "logout")
+            .and(warp::post())
+            .and(with_db(db.clone()))
+            .and(auth::token())
+            .and_then(auth::logout))
+        .or(warp::path!("api" / "client" / "new")
+            .and(with_db(db.clone()))
+            .and(auth::token())
+            .and_then(handler::new_client))
         .or(with_db(db.clone())
             .and(warp::path!("api" / "client" / "delete" / String))
             .and(warp::delete())
+            .and(auth::with_role(db.clone()))
             .and_then(handler::delete_client))
+        .or(with_db(db.clone())
+            .and(
+                warp::path!("api" / "export" / "measure" / i32)
+                    .map(ExportTarget::Measure)
+                    .or(warp::path!("api" / "export" / "series" / i32).map(ExportTarget::Series))
+                    .unify(),
+            )
+            .and(warp::get())
+            .and(auth::with_role(db.clone()))
+            .and_then(handler::export_csv))
+        .or(with_db(db.clone())
+            .and(warp::path!("api" / "archive" / "bow" / i32))
+            .and(warp::get())
+            .and(auth::with_role(db.clone()))
+            .and_then(handler::export_archive))
+        .or(with_db(db.clone())
+            .and(warp::path!("api" / "archive" / "import"))
+            .and(warp::post())
+            .and(auth::with_role(db.clone()))
+            .and(warp::body::content_length_limit(ARCHIVE_SIZE_LIMIT))
+            .and(warp::body::json())
+            .and_then(handler::import_archive))
+        .or(warp::path!("api" / "schema")
+            .map(|| false)
+            .or(warp::path!("api" / "schema.ts").map(|| true))
+            .unify()
+            .and(warp::get())
+            .and_then(handler::protocol_schema))
         .or(with_db(db.clone())
             .and(warp::ws())
             .and(warp::path!("ws" / String))
+            .and(auth::token())
             .and_then(handler::ws_connect))
-        .or_else(|_| async { Err(warp::reject()) });
+        .or_else(|_| async { Err(warp::reject()) })
+        .or(rest::routes(db.clone()))
+        .or(frontend::routes(db.frontend.clone()));
     routes
 }
 
@@ -99,64 +219,205 @@ fn with_db<F: database::ArrowDB>(
     warp::any().map(move || db.clone())
 }
 
-pub async fn new(
-    config: &Configuration,
-) -> Result<
-    (
-        Builder<impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone>,
-        Webserver<impl database::ArrowDB>,
-    ),
-    Box<(dyn Error)>,
-> {
-    Builder::from_factory(config, Box::new(register_routes)).await
+pub fn new<D: database::ArrowDB + 'static>(
+    db: D,
+    control_tx: ControlSender,
+    config: &ServerConfiguration,
+) -> (
+    Builder<impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone>,
+    Webserver<D>,
+) {
+    Builder::from_factory(db, control_tx, config, Box::new(register_routes))
 }
 
 impl<F> Builder<F>
 where
-    F: Filter + Clone + Send + Sync + 'static,
+    F: Filter<Error = warp::Rejection> + Clone + Send + Sync + 'static,
     F::Extract: warp::Reply,
 {
     //type RouteType = impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
-    pub async fn from_factory(
-        config: &Configuration,
-        routes_factory: Box<dyn Fn(Webserver<database::PgArrowDB>) -> F>,
-    ) -> Result<(Self, Webserver<database::PgArrowDB>), Box<dyn Error>> {
+    pub fn from_factory<D: database::ArrowDB + 'static>(
+        db: D,
+        control_tx: ControlSender,
+        config: &ServerConfiguration,
+        routes_factory: Box<dyn Fn(Webserver<D>) -> F>,
+    ) -> (Self, Webserver<D>) {
         let (tx, rx): (mpsc::Sender<()>, _) = mpsc::channel(SHUTDOWN_CHANNEL_SIZE);
-        let db_conn_str = format!(
-            "postgres://{}:{}@{}:{}/{}",
-            config.db.user, config.db.password, config.db.host, config.db.port, config.db.db
-        );
-        let pool = PgPool::connect(&db_conn_str).await?;
-        let db = database::PgArrowDB::new(pool);
-
-        let wsrv = Webserver::new(tx, db);
+        let mut wsrv = Webserver::new(tx, control_tx, db);
+        wsrv.authentication = config.authentication;
+        wsrv.frontend = config.static_dir.as_ref().map(PathBuf::from);
+        if !config.authentication {
+            warn!("authentication is disabled, every client is an operator, set \"server\": {{\"authentication\": true}} to require a login");
+        }
         let notification_srv = wsrv.clone();
 
         notification_srv.listen();
+        wsrv.clone().sweep();
 
-        let server = warp::serve(routes_factory(wsrv.clone()));
         let instance = Self {
-            server,
+            routes: routes_factory(wsrv.clone()),
             shutdown_rx: rx,
         };
-        Ok((instance, wsrv))
+        (instance, wsrv)
     }
 
+    /// Listens on all addresses, serving https if `tls` is set. The returned
+    /// future completes once every listener is shut down.
     pub fn bind(
         self,
-        addr: impl Into<SocketAddr> + 'static,
-    ) -> Result<(SocketAddr, impl std::future::Future<Output = ()>), impl Error> {
-        let (server, mut rx) = (self.server, self.shutdown_rx);
-        server
-            .try_bind_with_graceful_shutdown(addr, async move {
+        addrs: &[SocketAddr],
+        tls: Option<&TlsConfiguration>,
+    ) -> Result<impl Future<Output = ()>, Box<dyn Error>> {
+        if addrs.is_empty() {
+            return Err("no address to listen on, set at least one server address.".into());
+        }
+        let (routes, mut rx) = (self.routes, self.shutdown_rx);
+        let (stop_tx, _) = broadcast::channel::<()>(1);
+        let mut servers = Vec::new();
+        for addr in addrs {
+            let mut stop = stop_tx.subscribe();
+            let signal = async move {
+                let _ = stop.recv().await;
+            };
+            let server = warp::serve(routes.clone());
+            let bound = match tls {
+                None => server
+                    .try_bind_with_graceful_shutdown(*addr, signal)
+                    .map(|(addr, server)| (addr, server.boxed())),
+                #[cfg(feature = "tls")]
+                Some(tls) => server
+                    .tls()
+                    .cert_path(&tls.cert)
+                    .key_path(&tls.key)
+                    .try_bind_with_graceful_shutdown(*addr, signal)
+                    .map(|(addr, server)| (addr, server.boxed())),
+                #[cfg(not(feature = "tls"))]
+                Some(_) => {
+                    return Err("TLS not available, rebuild with '--features tls'.".into());
+                }
+            };
+            let (addr, server) = bound.map_err(|e| {
+                error!("cannot create webserver on {}: '{}'", addr, e);
+                e
+            })?;
+            info!("listening on {}", addr);
+            servers.push(server);
+        }
+        Ok(async move {
+            let shutdown = async move {
                 rx.recv().await;
                 rx.close();
                 //empty recv buffer
                 while let Some(_) = rx.recv().await {}
+                let _ = stop_tx.send(());
+            };
+            futures::join!(futures::future::join_all(servers), shutdown);
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::database::{ArrowDB, InMemoryArrowDB};
+    use super::*;
+    use crate::message::control_channel;
+    use crate::models::{Arrow, Bow, Measure, MeasurePoint, MeasureSeries};
+    use time::OffsetDateTime;
+    use warp::http::StatusCode;
+
+    /// Memory database holding one measure with two points.
+    async fn measure_db() -> InMemoryArrowDB {
+        let db = InMemoryArrowDB::new();
+        let bow = db
+            .add_bow(Bow {
+                id: -1,
+                name: "bow".into(),
+                max_draw_distance: 0.8,
+                remainder_arrow_length: 0.1,
+            })
+            .await
+            .unwrap();
+        let series = db
+            .add_measure_series(MeasureSeries {
+                id: -1,
+                name: "series".into(),
+                rest_position: 0.2,
+                draw_distance: Some(0.5),
+                draw_force: None,
+                time: OffsetDateTime::now_utc(),
+                bow_id: bow.id,
+            })
+            .await
+            .unwrap();
+        let arrow = db
+            .add_arrow(Arrow {
+                id: -1,
+                name: None,
+                head_weight: None,
+                spline: None,
+                feather_length: None,
+                feather_type: None,
+                length: 0.75,
+                weight: 0.025,
+                bow_id: bow.id,
             })
-            .or_else(|e| {
-                error!("cannot create webserver: '{}'", e);
-                Err(e)
+            .await
+            .unwrap();
+        let measure = db
+            .add_measure(Measure {
+                id: -1,
+                measure_interval: 0.01,
+                measure_series_id: series.id,
+                arrow_id: arrow.id,
+            })
+            .await
+            .unwrap();
+        let points = (0..2)
+            .map(|i| MeasurePoint {
+                id: -1,
+                time: i * 10_000,
+                draw_distance: 0.25,
+                force: 2.5,
+                measure_id: -1,
             })
+            .collect();
+        db.add_measure_points(measure.id, points).await.unwrap();
+        db
+    }
+
+    #[tokio::test]
+    async fn test_measure_export() {
+        let (tx, _rx) = mpsc::channel(8);
+        let (control_tx, _control_rx) = control_channel(8);
+        let srv = Webserver::new(tx, control_tx, measure_db().await);
+        let routes = register_routes(srv);
+
+        let reply = warp::test::request()
+            .path("/api/export/measure/1")
+            .reply(&routes)
+            .await;
+        assert_eq!(reply.status(), StatusCode::OK);
+        assert_eq!(
+            reply.headers()["content-disposition"],
+            "attachment; filename=\"measure-1.csv\""
+        );
+        assert_eq!(
+            reply.body(),
+            "time [s],draw_distance [m],force [N]\n0,0.25,2.5\n0.01,0.25,2.5\n"
+        );
+
+        let reply = warp::test::request()
+            .path("/api/export/measure/9")
+            .reply(&routes)
+            .await;
+        assert_eq!(reply.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_bind_needs_an_address() {
+        let (control_tx, _control_rx) = control_channel(8);
+        let config = ServerConfiguration::default();
+        let (builder, _srv) = new(InMemoryArrowDB::new(), control_tx, &config);
+        assert!(builder.bind(&[], None).is_err());
     }
 }
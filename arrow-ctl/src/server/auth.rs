@@ -0,0 +1,274 @@
+use super::super::models::{Role, User};
+use super::database::ArrowDB;
+use super::handler::{self, WSError};
+use super::rest;
+use super::Webserver;
+
+use log::{info, warn};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+use warp::reply::{json, Response};
+use warp::{Filter, Reply};
+
+/// Sessions end this long after the login.
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(12 * 60 * 60);
+
+const HASH_ALGORITHM: &str = "pbkdf2-sha256";
+const HASH_ITERATIONS: u32 = 100_000;
+const HASH_LENGTH: usize = 32;
+const SALT_LENGTH: usize = 16;
+const TOKEN_LENGTH: usize = 32;
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub user: String,
+    pub role: Role,
+    pub expires: Instant,
+}
+
+/// Logged in users by session token.
+pub type Sessions = Arc<RwLock<HashMap<String, Session>>>;
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct Credentials {
+    pub name: String,
+    pub password: String,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct LoginResponse {
+    token: String,
+    role: Role,
+}
+
+/// Hashes the password with a random salt, the result is stored as
+/// `pbkdf2-sha256$<iterations>$<salt>$<hash>` with hex encoded salt and hash.
+pub fn hash_password(password: &str) -> String {
+    let salt = random_bytes(SALT_LENGTH);
+    let mut hash = [0u8; HASH_LENGTH];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(HASH_ITERATIONS).unwrap(),
+        &salt,
+        password.as_bytes(),
+        &mut hash,
+    );
+    format!(
+        "{}${}${}${}",
+        HASH_ALGORITHM,
+        HASH_ITERATIONS,
+        hex::encode(salt),
+        hex::encode(hash)
+    )
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    let parts: Vec<&str> = password_hash.split('$').collect();
+    let (iterations, salt, hash) = match parts[..] {
+        [HASH_ALGORITHM, iterations, salt, hash] => (iterations, salt, hash),
+        _ => return false,
+    };
+    let iterations = match iterations.parse().ok().and_then(NonZeroU32::new) {
+        Some(iterations) => iterations,
+        None => return false,
+    };
+    match (hex::decode(salt), hex::decode(hash)) {
+        (Ok(salt), Ok(hash)) => pbkdf2::verify(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            &salt,
+            password.as_bytes(),
+            &hash,
+        )
+        .is_ok(),
+        _ => false,
+    }
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random number generator failed");
+    bytes
+}
+
+/// Session token of a request, given as `Authorization: Bearer <token>`
+/// header or as `token` query parameter, as browsers cannot set headers on
+/// websocket connections.
+pub fn token() -> impl Filter<Extract = (Option<String>,), Error = Infallible> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(
+            warp::query::<HashMap<String, String>>()
+                .or(warp::any().map(HashMap::new))
+                .unify(),
+        )
+        .map(
+            |header: Option<String>, mut query: HashMap<String, String>| {
+                header
+                    .and_then(|h| h.strip_prefix("Bearer ").map(str::to_string))
+                    .or_else(|| query.remove("token"))
+            },
+        )
+        .or(warp::any().map(|| None))
+        .unify()
+}
+
+/// Role of the session the request belongs to, `None` if not logged in. With
+/// authentication disabled everyone is an operator.
+pub fn with_role<F: ArrowDB>(
+    srv: Webserver<F>,
+) -> impl Filter<Extract = (Option<Role>,), Error = Infallible> + Clone {
+    token().and_then(move |token| {
+        let srv = srv.clone();
+        async move { Ok::<_, Infallible>(role(&srv, token).await) }
+    })
+}
+
+pub async fn role<F: ArrowDB>(srv: &Webserver<F>, token: Option<String>) -> Option<Role> {
+    if !srv.authentication {
+        return Some(Role::Operator);
+    }
+    let sessions = srv.sessions.read().await;
+    sessions
+        .get(&token?)
+        .filter(|s| s.expires > Instant::now())
+        .map(|s| s.role)
+}
+
+/// Fails if the role is not at least `needed`.
+pub fn authorize(role: Option<Role>, needed: Role) -> Result<(), WSError> {
+    match role {
+        None => Err(WSError::Unauthorized),
+        Some(role) if role < needed => Err(WSError::Forbidden(needed)),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Starts a session for the user, replies with its token.
+pub async fn login<F: ArrowDB>(
+    srv: Webserver<F>,
+    credentials: Credentials,
+) -> Result<Response, Infallible> {
+    let user = match srv.db.get_user(credentials.name.clone()).await {
+        Ok(user) => user,
+        Err(sqlx::Error::RowNotFound) => {
+            warn!(target: "arrow::web::auth", "login of unknown user '{}'", credentials.name);
+            return Ok(rest::error_reply(WSError::Credentials));
+        }
+        Err(e) => return Ok(rest::error_reply(e.into())),
+    };
+    if !verify_password(&credentials.password, &user.password_hash) {
+        warn!(target: "arrow::web::auth", "wrong password for user '{}'", user.name);
+        return Ok(rest::error_reply(WSError::Credentials));
+    }
+    Ok(json(&start_session(&srv, &user).await).into_response())
+}
+
+async fn start_session<F: ArrowDB>(srv: &Webserver<F>, user: &User) -> LoginResponse {
+    let token = hex::encode(random_bytes(TOKEN_LENGTH));
+    let session = Session {
+        user: user.name.clone(),
+        role: user.role,
+        expires: Instant::now() + SESSION_TIMEOUT,
+    };
+    srv.sessions.write().await.insert(token.clone(), session);
+    info!(target: "arrow::web::auth", "{} logged in as {}", user.name, user.role.as_str());
+    LoginResponse {
+        token,
+        role: user.role,
+    }
+}
+
+pub async fn logout<F: ArrowDB>(
+    srv: Webserver<F>,
+    token: Option<String>,
+) -> Result<impl Reply, Infallible> {
+    let token = token.unwrap_or_default();
+    let session = srv.sessions.write().await.remove(&token);
+    if let Some(session) = session {
+        info!(target: "arrow::web::auth", "{} logged out", session.user);
+        handler::close_sessions(&srv, &[token]).await;
+    }
+    Ok(warp::http::StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::database::traits::MockDB;
+    use super::*;
+
+    #[test]
+    fn passwords_are_salted() {
+        let hash = hash_password("secret");
+        assert!(hash.starts_with("pbkdf2-sha256$100000$"));
+        assert_ne!(hash, hash_password("secret"));
+        assert!(verify_password("secret", &hash));
+        assert!(!verify_password("Secret", &hash));
+        assert!(!verify_password("secret", "plain"));
+    }
+
+    #[test]
+    fn operators_may_do_everything() {
+        assert!(authorize(Some(Role::Operator), Role::Operator).is_ok());
+        assert!(authorize(Some(Role::Viewer), Role::Viewer).is_ok());
+        assert!(matches!(
+            authorize(Some(Role::Viewer), Role::Operator),
+            Err(WSError::Forbidden(Role::Operator))
+        ));
+        assert!(matches!(
+            authorize(None, Role::Viewer),
+            Err(WSError::Unauthorized)
+        ));
+    }
+
+    #[tokio::test]
+    async fn login_starts_session() {
+        // login and logout work on clones of the server
+        fn mock_db(hash: String) -> MockDB {
+            let mut db = MockDB::new();
+            let password_hash = hash.clone();
+            db.expect_get_user().returning(move |name| {
+                Ok(User {
+                    id: 1,
+                    name,
+                    password_hash: password_hash.clone(),
+                    role: Role::Operator,
+                })
+            });
+            db.expect_clone().returning(move || mock_db(hash.clone()));
+            db
+        }
+        let db = mock_db(hash_password("secret"));
+        let (tx, _rx) = tokio::sync::mpsc::channel(8);
+        let (control_tx, _control_rx) = crate::message::control_channel(8);
+        let mut srv = Webserver::new(tx, control_tx, db);
+        srv.authentication = true;
+
+        let credentials = |password: &str| Credentials {
+            name: "staff".into(),
+            password: password.into(),
+        };
+        let reply = login(srv.clone(), credentials("wrong")).await.unwrap();
+        assert_eq!(reply.status(), warp::http::StatusCode::UNAUTHORIZED);
+        assert!(srv.sessions.read().await.is_empty());
+
+        let reply = login(srv.clone(), credentials("secret")).await.unwrap();
+        assert_eq!(reply.status(), warp::http::StatusCode::OK);
+        let token = srv.sessions.read().await.keys().next().cloned();
+        assert_eq!(role(&srv, token.clone()).await, Some(Role::Operator));
+        logout(srv.clone(), token.clone()).await.unwrap();
+        assert_eq!(role(&srv, token).await, None);
+    }
+}
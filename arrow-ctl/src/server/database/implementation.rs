@@ -1,17 +1,22 @@
 use super::ArrowDB;
+use crate::config::DBConfiguration;
 use crate::message::WSUpdate;
-use crate::message::WSUpdate::*;
+use crate::message::WSUpdate::{
+    ArrowList, BowList, Health, MeasureList, MeasureResultList, MeasureSeriesList, Removed, Resync,
+};
 use crate::models::*;
 
 use async_trait::async_trait;
 
-use sqlx::postgres::PgListener;
+use sqlx::migrate::{Migrate, MigrateError, Migration, Migrator};
+use sqlx::postgres::{PgListener, PgNotification};
 use sqlx::PgPool;
 
 use tokio::sync::mpsc::UnboundedSender;
-use tokio_stream::StreamExt;
 
-use log::trace;
+use log::{info, trace, warn};
+use std::collections::HashSet;
+use std::time::Duration;
 
 macro_rules! table_matcher {
     ($result:ident, $data:ident, $pool:ident, $(($table:literal, $model:ident, $update:ident)),+) => {
@@ -40,6 +45,32 @@ macro_rules! query_table {
     };
 }
 
+/// Schema migrations in `arrow-ctl/migrations`, embedded at compile time.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Delays between attempts to reconnect the notification listener.
+const LISTEN_BACKOFF_MIN: Duration = Duration::from_millis(500);
+const LISTEN_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// Parses the ids of a notification payload, given as single ids and ranges
+/// like `1-500,502`. `*` stands for more changes than fit into one
+/// notification and yields `None`.
+fn parse_ids(ids: &str) -> Option<Vec<i32>> {
+    if ids == "*" {
+        return None;
+    }
+    let mut parsed = Vec::new();
+    for range in ids.split(',') {
+        let mut bounds = range.splitn(2, '-').map(|id| id.parse::<i32>());
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(first)), Some(Ok(last))) => parsed.extend(first..=last),
+            (Some(Ok(id)), None) => parsed.push(id),
+            _ => warn!(target: "arrow::db", "invalid id in notification: '{}'", range),
+        }
+    }
+    Some(parsed)
+}
+
 #[derive(Clone)]
 pub struct PgArrowDB {
     pool: PgPool,
@@ -49,6 +80,82 @@ impl PgArrowDB {
     pub fn new(pool: PgPool) -> Self {
         Self { pool }
     }
+
+    pub async fn connect(config: &DBConfiguration) -> Result<Self, sqlx::Error> {
+        let db_conn_str = format!(
+            "postgres://{}:{}@{}:{}/{}",
+            config.user, config.password, config.host, config.port, config.db
+        );
+        let pool = PgPool::connect(&db_conn_str).await?;
+        Ok(Self::new(pool))
+    }
+
+    /// Applies all pending migrations, existing data is kept.
+    pub async fn migrate(&self) -> Result<(), MigrateError> {
+        MIGRATOR.run(&self.pool).await?;
+        info!(target: "arrow::db", "database schema is up to date");
+        Ok(())
+    }
+
+    pub async fn pending_migrations(&self) -> Result<Vec<&'static Migration>, MigrateError> {
+        let mut conn = self.pool.acquire().await?;
+        conn.ensure_migrations_table().await?;
+        let applied: HashSet<i64> = conn
+            .list_applied_migrations()
+            .await?
+            .iter()
+            .map(|m| m.version)
+            .collect();
+        Ok(MIGRATOR
+            .iter()
+            .filter(|m| !applied.contains(&m.version))
+            .collect())
+    }
+
+    async fn subscribe(&self) -> Result<PgListener, sqlx::Error> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen_all(vec!["update", "delete"]).await?;
+        Ok(listener)
+    }
+
+    /// Sends the rows named in the notification to the channel, returns
+    /// false once the channel is closed.
+    async fn forward(
+        &self,
+        notification: PgNotification,
+        channel: &UnboundedSender<WSUpdate>,
+    ) -> bool {
+        trace!("notification: {:#?}", notification);
+        let mut split = notification.payload().splitn(2, ",");
+        if let (Some(table), Some(ids)) = (split.next(), split.next()) {
+            let ids = match parse_ids(ids) {
+                Some(ids) => ids,
+                None => return channel.send(Resync {}).is_ok(),
+            };
+            if notification.channel() == "delete" {
+                let removed = Removed {
+                    table: table.into(),
+                    ids,
+                };
+                return channel.send(removed).is_ok();
+            }
+            let pool = &self.pool;
+            let response = table_matcher!(
+                table,
+                ids,
+                pool,
+                ("bow", Bow, BowList),
+                ("arrow", Arrow, ArrowList),
+                ("measure_series", MeasureSeries, MeasureSeriesList),
+                ("measure_result", MeasureResult, MeasureResultList),
+                ("measure", Measure, MeasureList)
+            );
+            if let Some(update) = response {
+                return channel.send(update).is_ok();
+            }
+        }
+        true
+    }
 }
 
 #[async_trait]
@@ -60,6 +167,13 @@ impl ArrowDB for PgArrowDB {
         Ok(bows)
     }
 
+    async fn get_bow(&self, id: i32) -> Result<Bow, sqlx::Error> {
+        let bow = sqlx::query_as!(Bow, "SELECT * FROM bow WHERE id = $1", id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(bow)
+    }
+
     async fn add_bow(&self, bow: Bow) -> Result<Bow, sqlx::Error> {
         let rec = sqlx::query!(
             r#"INSERT INTO bow 
@@ -75,6 +189,30 @@ impl ArrowDB for PgArrowDB {
         Ok(Bow { id: rec.id, ..bow })
     }
 
+    async fn update_bow(&self, bow: Bow) -> Result<Bow, sqlx::Error> {
+        let bow = sqlx::query_as!(
+            Bow,
+            r#"UPDATE bow
+            SET name = $2, max_draw_distance = $3, remainder_arrow_length = $4
+            WHERE id = $1
+            RETURNING *"#,
+            bow.id,
+            bow.name,
+            bow.max_draw_distance,
+            bow.remainder_arrow_length
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(bow)
+    }
+
+    async fn delete_bow(&self, id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM bow WHERE id = $1 RETURNING id", id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(())
+    }
+
     async fn list_measurement_series(&self, id: i32) -> Result<Vec<MeasureSeries>, sqlx::Error> {
         let series = sqlx::query_as!(
             MeasureSeries,
@@ -86,6 +224,17 @@ impl ArrowDB for PgArrowDB {
         Ok(series)
     }
 
+    async fn get_measure_series(&self, id: i32) -> Result<MeasureSeries, sqlx::Error> {
+        let series = sqlx::query_as!(
+            MeasureSeries,
+            "SELECT * FROM measure_series WHERE id = $1",
+            id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(series)
+    }
+
     async fn add_measure_series(
         &self,
         series: MeasureSeries,
@@ -110,6 +259,29 @@ impl ArrowDB for PgArrowDB {
         })
     }
 
+    async fn rename_measure_series(
+        &self,
+        id: i32,
+        name: String,
+    ) -> Result<MeasureSeries, sqlx::Error> {
+        let series = sqlx::query_as!(
+            MeasureSeries,
+            "UPDATE measure_series SET name = $2 WHERE id = $1 RETURNING *",
+            id,
+            name
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(series)
+    }
+
+    async fn delete_measure_series(&self, id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM measure_series WHERE id = $1 RETURNING id", id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(())
+    }
+
     async fn list_arrows(&self, id: i32) -> Result<Vec<Arrow>, sqlx::Error> {
         let arrows = sqlx::query_as!(Arrow, "SELECT * FROM arrow WHERE bow_id = $1", id)
             .fetch_all(&self.pool)
@@ -117,6 +289,13 @@ impl ArrowDB for PgArrowDB {
         Ok(arrows)
     }
 
+    async fn get_arrow(&self, id: i32) -> Result<Arrow, sqlx::Error> {
+        let arrow = sqlx::query_as!(Arrow, "SELECT * FROM arrow WHERE id = $1", id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(arrow)
+    }
+
     async fn add_arrow(&self, arrow: Arrow) -> Result<Arrow, sqlx::Error> {
         let rec = sqlx::query!(
             r#"INSERT INTO arrow 
@@ -140,6 +319,36 @@ impl ArrowDB for PgArrowDB {
         })
     }
 
+    async fn update_arrow(&self, arrow: Arrow) -> Result<Arrow, sqlx::Error> {
+        let arrow = sqlx::query_as!(
+            Arrow,
+            r#"UPDATE arrow
+            SET name = $2, head_weight = $3, spline = $4, feather_length = $5,
+            feather_type = $6, length = $7, weight = $8, bow_id = $9
+            WHERE id = $1
+            RETURNING *"#,
+            arrow.id,
+            arrow.name,
+            arrow.head_weight,
+            arrow.spline,
+            arrow.feather_length,
+            arrow.feather_type,
+            arrow.length,
+            arrow.weight,
+            arrow.bow_id,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(arrow)
+    }
+
+    async fn delete_arrow(&self, id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM arrow WHERE id = $1 RETURNING id", id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(())
+    }
+
     async fn list_measures(&self, id: i32) -> Result<Vec<Measure>, sqlx::Error> {
         let measures = sqlx::query_as!(
             Measure,
@@ -151,6 +360,13 @@ impl ArrowDB for PgArrowDB {
         Ok(measures)
     }
 
+    async fn get_measure(&self, id: i32) -> Result<Measure, sqlx::Error> {
+        let measure = sqlx::query_as!(Measure, "SELECT * FROM measure WHERE id = $1", id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(measure)
+    }
+
     async fn add_measure(&self, measure: Measure) -> Result<Measure, sqlx::Error> {
         let rec = sqlx::query!(
             r#"INSERT INTO measure 
@@ -169,10 +385,17 @@ impl ArrowDB for PgArrowDB {
         })
     }
 
+    async fn delete_measure(&self, id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM measure WHERE id = $1 RETURNING id", id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(())
+    }
+
     async fn list_measure_points(&self, id: i32) -> Result<Vec<MeasurePoint>, sqlx::Error> {
         let measure_points = sqlx::query_as!(
             MeasurePoint,
-            "SELECT * FROM measure_point WHERE measure_id = $1",
+            "SELECT * FROM measure_point WHERE measure_id = $1 ORDER BY time, id",
             id
         )
         .fetch_all(&self.pool)
@@ -180,46 +403,196 @@ impl ArrowDB for PgArrowDB {
         Ok(measure_points)
     }
 
+    async fn add_measure_points(
+        &self,
+        measure_id: i32,
+        points: Vec<MeasurePoint>,
+    ) -> Result<Vec<MeasurePoint>, sqlx::Error> {
+        if points.is_empty() {
+            return Ok(points);
+        }
+        let time: Vec<i64> = points.iter().map(|p| p.time).collect();
+        let draw_distance: Vec<f64> = points.iter().map(|p| p.draw_distance).collect();
+        let force: Vec<f64> = points.iter().map(|p| p.force).collect();
+        let recs = sqlx::query!(
+            r#"INSERT INTO measure_point
+            (time, draw_distance, force, measure_id)
+            SELECT time, draw_distance, force, $4
+            FROM UNNEST($1::BIGINT[], $2::DOUBLE PRECISION[], $3::DOUBLE PRECISION[])
+            AS point (time, draw_distance, force)
+            RETURNING id"#,
+            &time,
+            &draw_distance,
+            &force,
+            measure_id,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(points
+            .into_iter()
+            .zip(recs)
+            .map(|(point, rec)| MeasurePoint {
+                id: rec.id,
+                measure_id,
+                ..point
+            })
+            .collect())
+    }
+
+    async fn list_measure_results(&self, id: i32) -> Result<Vec<MeasureResult>, sqlx::Error> {
+        let results = sqlx::query_as!(
+            MeasureResult,
+            r#"SELECT measure_result.* FROM measure_result
+            JOIN measure ON measure.id = measure_result.measure_id
+            WHERE measure.measure_series_id = $1"#,
+            id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(results)
+    }
+
+    async fn add_measure_result(
+        &self,
+        result: MeasureResult,
+    ) -> Result<MeasureResult, sqlx::Error> {
+        let rec = sqlx::query!(
+            r#"INSERT INTO measure_result 
+            (measure_id, arrow_speed, potential_energy, kinetic_energy, efficiency, wasted_energy)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id"#,
+            result.measure_id,
+            result.arrow_speed,
+            result.potential_energy,
+            result.kinetic_energy,
+            result.efficiency,
+            result.wasted_energy,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(MeasureResult {
+            id: rec.id,
+            ..result
+        })
+    }
+
+    async fn get_user(&self, name: String) -> Result<User, sqlx::Error> {
+        let rec = sqlx::query!(
+            "SELECT id, name, password_hash, role FROM account WHERE name = $1",
+            name
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(User {
+            id: rec.id,
+            name: rec.name,
+            password_hash: rec.password_hash,
+            role: rec
+                .role
+                .parse()
+                .map_err(|e: String| sqlx::Error::Decode(e.into()))?,
+        })
+    }
+
+    async fn add_user(&self, user: User) -> Result<User, sqlx::Error> {
+        let rec = sqlx::query!(
+            r#"INSERT INTO account
+            (name, password_hash, role)
+            VALUES ($1, $2, $3)
+            RETURNING id"#,
+            user.name,
+            user.password_hash,
+            user.role.as_str(),
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(User { id: rec.id, ..user })
+    }
+
+    /// Forwards the change notifications to the channel. A lost connection is
+    /// re-established with exponential backoff, the clients are told about
+    /// the outage with a `Health` update and asked to `Resync` afterwards, as
+    /// notifications sent in the meantime are lost.
     async fn listener(self, channel: UnboundedSender<WSUpdate>) -> Result<(), sqlx::Error> {
-        let mut listener = PgListener::connect_with(&self.pool).await?;
-        listener.listen_all(vec!["update"]).await?;
-        let mut stream = listener.into_stream();
-        while let Some(notification) = stream.try_next().await? {
-            trace!("notification: {:#?}", notification);
-            let mut split = notification.payload().splitn(2, ",");
-            if let (Some(table), Some(ids)) = (split.next(), split.next()) {
-                let ids: Vec<i32> = ids
-                    .split(",")
-                    .filter_map(|id| id.parse::<i32>().ok())
-                    .collect();
-                let pool = &self.pool;
-                let response = table_matcher!(
-                    table,
-                    ids,
-                    pool,
-                    ("bow", Bow, BowList),
-                    ("arrow", Arrow, ArrowList),
-                    ("measure_series", MeasureSeries, MeasureSeriesList),
-                    ("measure_point", MeasurePoint, MeasurePointList),
-                    ("measure", Measure, MeasureList)
-                );
-                if let Some(update) = response {
-                    let send = channel.send(update);
-                    if send.is_err() {
+        let mut backoff = LISTEN_BACKOFF_MIN;
+        let mut state = ConnectionState::Connected;
+        loop {
+            let mut listener = match self.subscribe().await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    warn!(target: "arrow::db", "cannot listen for notifications, retrying in {:?}: '{}'", backoff, e);
+                    if state == ConnectionState::Connected {
+                        state = ConnectionState::Disconnected;
+                        if channel.send(Health { database: state }).is_err() {
+                            return Ok(());
+                        }
+                    }
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(LISTEN_BACKOFF_MAX);
+                    continue;
+                }
+            };
+            backoff = LISTEN_BACKOFF_MIN;
+            if state == ConnectionState::Disconnected {
+                info!(target: "arrow::db", "listening for notifications again");
+                state = ConnectionState::Connected;
+                if channel.send(Health { database: state }).is_err()
+                    || channel.send(Resync {}).is_err()
+                {
+                    return Ok(());
+                }
+            }
+            loop {
+                match listener.try_recv().await {
+                    Ok(Some(notification)) => {
+                        if !self.forward(notification, &channel).await {
+                            return Ok(());
+                        }
+                    }
+                    Ok(None) => {
+                        warn!(target: "arrow::db", "lost connection to the database");
+                        break;
+                    }
+                    Err(e) => {
+                        warn!(target: "arrow::db", "lost connection to the database: '{}'", e);
                         break;
                     }
                 }
             }
+            state = ConnectionState::Disconnected;
+            if channel.send(Health { database: state }).is_err() {
+                return Ok(());
+            }
         }
-        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
-    pub async fn connect_and_configure(conn_url: &str) -> Result<PgArrowDB, sqlx::Error> {
+    use super::*;
+
+    pub async fn connect_and_configure(conn_url: &str) -> Result<PgArrowDB, MigrateError> {
         let pool = PgPool::connect(conn_url).await?;
-        sqlx::migrate!("db/migrations").run(&pool).await?;
-        Ok(PgArrowDB::new(pool))
+        let db = PgArrowDB::new(pool);
+        db.migrate().await?;
+        Ok(db)
+    }
+
+    #[test]
+    fn notification_ids_are_expanded() {
+        assert_eq!(parse_ids("3"), Some(vec![3]));
+        assert_eq!(parse_ids("1-4,7,9-10"), Some(vec![1, 2, 3, 4, 7, 9, 10]));
+        assert_eq!(parse_ids("1,x,2"), Some(vec![1, 2]));
+        assert_eq!(parse_ids("*"), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a running postgres, set DATABASE_URL"]
+    async fn migrations_are_idempotent() {
+        let url = std::env::var("DATABASE_URL").unwrap();
+        let db = connect_and_configure(&url).await.unwrap();
+        db.migrate().await.unwrap();
+        assert!(db.pending_migrations().await.unwrap().is_empty());
+        db.list_bows().await.unwrap();
     }
 }
@@ -0,0 +1,545 @@
+use super::notifier::Notifier;
+use super::ArrowDB;
+use crate::message::WSUpdate;
+use crate::models::*;
+
+use async_trait::async_trait;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Rows of a single table, ids are assigned like a postgres `SERIAL`.
+struct Table<T> {
+    rows: BTreeMap<i32, T>,
+    next_id: i32,
+}
+
+impl<T: Clone> Table<T> {
+    fn insert(&mut self, row: impl FnOnce(i32) -> T) -> T {
+        self.next_id += 1;
+        let row = row(self.next_id);
+        self.rows.insert(self.next_id, row.clone());
+        row
+    }
+
+    fn get(&self, id: i32) -> Result<T, sqlx::Error> {
+        self.rows.get(&id).cloned().ok_or(sqlx::Error::RowNotFound)
+    }
+
+    fn update(&mut self, id: i32, update: impl FnOnce(&mut T)) -> Result<T, sqlx::Error> {
+        let row = self.rows.get_mut(&id).ok_or(sqlx::Error::RowNotFound)?;
+        update(row);
+        Ok(row.clone())
+    }
+
+    fn filter(&self, predicate: impl Fn(&T) -> bool) -> Vec<T> {
+        self.rows
+            .values()
+            .filter(|r| predicate(r))
+            .cloned()
+            .collect()
+    }
+
+    /// Removes all rows matching the predicate and returns their ids.
+    fn remove(&mut self, predicate: impl Fn(&T) -> bool) -> Vec<i32> {
+        let ids: Vec<i32> = self
+            .rows
+            .iter()
+            .filter(|(_, r)| predicate(r))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &ids {
+            self.rows.remove(id);
+        }
+        ids
+    }
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Self {
+            rows: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+}
+
+/// Tables and ids of deleted rows, notified after the tables are unlocked.
+type Removed = Vec<(&'static str, Vec<i32>)>;
+
+#[derive(Default)]
+struct Tables {
+    bows: Table<Bow>,
+    series: Table<MeasureSeries>,
+    arrows: Table<Arrow>,
+    measures: Table<Measure>,
+    points: Table<MeasurePoint>,
+    results: Table<MeasureResult>,
+    users: Table<User>,
+}
+
+impl Tables {
+    /// Deletes the measures and everything referencing them, like the
+    /// `ON DELETE CASCADE` constraints of the postgres schema.
+    fn remove_measures(
+        &mut self,
+        predicate: impl Fn(&Measure) -> bool,
+        removed: &mut Removed,
+    ) -> Vec<i32> {
+        let measures = self.measures.remove(predicate);
+        self.points.remove(|p| measures.contains(&p.measure_id));
+        let results = self.results.remove(|r| measures.contains(&r.measure_id));
+        removed.push(("measure_result", results));
+        removed.push(("measure", measures.clone()));
+        measures
+    }
+
+    fn remove_series(
+        &mut self,
+        predicate: impl Fn(&MeasureSeries) -> bool,
+        removed: &mut Removed,
+    ) -> Vec<i32> {
+        let series = self.series.remove(predicate);
+        self.remove_measures(|m| series.contains(&m.measure_series_id), removed);
+        removed.push(("measure_series", series.clone()));
+        series
+    }
+}
+
+fn duplicate(table: &str, name: &str) -> sqlx::Error {
+    sqlx::Error::Protocol(format!("{} with name '{}' already exists", table, name))
+}
+
+/// Database kept in memory, used for tests and running without postgres.
+/// All data is lost on shutdown.
+#[derive(Clone, Default)]
+pub struct InMemoryArrowDB {
+    tables: Arc<Mutex<Tables>>,
+    notifier: Notifier,
+}
+
+impl InMemoryArrowDB {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn tables(&self) -> std::sync::MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn notify(&self, update: WSUpdate) {
+        self.notifier.notify(update);
+    }
+
+    fn notify_removed(&self, removed: Removed) {
+        for (table, ids) in removed {
+            self.notifier.notify_removed(table, ids);
+        }
+    }
+}
+
+#[async_trait]
+impl ArrowDB for InMemoryArrowDB {
+    async fn list_bows(&self) -> Result<Vec<Bow>, sqlx::Error> {
+        Ok(self.tables().bows.filter(|_| true))
+    }
+
+    async fn get_bow(&self, id: i32) -> Result<Bow, sqlx::Error> {
+        self.tables().bows.get(id)
+    }
+
+    async fn add_bow(&self, bow: Bow) -> Result<Bow, sqlx::Error> {
+        let bow = {
+            let mut tables = self.tables();
+            if !tables.bows.filter(|b| b.name == bow.name).is_empty() {
+                return Err(duplicate("bow", &bow.name));
+            }
+            tables.bows.insert(|id| Bow { id, ..bow })
+        };
+        self.notify(WSUpdate::BowList(vec![bow.clone()]));
+        Ok(bow)
+    }
+
+    async fn update_bow(&self, bow: Bow) -> Result<Bow, sqlx::Error> {
+        let bow = {
+            let mut tables = self.tables();
+            if !tables
+                .bows
+                .filter(|b| b.name == bow.name && b.id != bow.id)
+                .is_empty()
+            {
+                return Err(duplicate("bow", &bow.name));
+            }
+            tables.bows.update(bow.id, |b| *b = bow.clone())?
+        };
+        self.notify(WSUpdate::BowList(vec![bow.clone()]));
+        Ok(bow)
+    }
+
+    async fn delete_bow(&self, id: i32) -> Result<(), sqlx::Error> {
+        let mut removed = Vec::new();
+        {
+            let mut tables = self.tables();
+            tables.bows.get(id)?;
+            tables.remove_series(|s| s.bow_id == id, &mut removed);
+            let arrows = tables.arrows.remove(|a| a.bow_id == id);
+            removed.push(("arrow", arrows));
+            tables.bows.remove(|b| b.id == id);
+            removed.push(("bow", vec![id]));
+        }
+        self.notify_removed(removed);
+        Ok(())
+    }
+
+    async fn list_measurement_series(&self, id: i32) -> Result<Vec<MeasureSeries>, sqlx::Error> {
+        Ok(self.tables().series.filter(|s| s.bow_id == id))
+    }
+
+    async fn get_measure_series(&self, id: i32) -> Result<MeasureSeries, sqlx::Error> {
+        self.tables().series.get(id)
+    }
+
+    async fn add_measure_series(
+        &self,
+        series: MeasureSeries,
+    ) -> Result<MeasureSeries, sqlx::Error> {
+        let series = {
+            let mut tables = self.tables();
+            tables.bows.get(series.bow_id)?;
+            if !tables.series.filter(|s| s.name == series.name).is_empty() {
+                return Err(duplicate("measure_series", &series.name));
+            }
+            tables.series.insert(|id| MeasureSeries { id, ..series })
+        };
+        self.notify(WSUpdate::MeasureSeriesList(vec![series.clone()]));
+        Ok(series)
+    }
+
+    async fn rename_measure_series(
+        &self,
+        id: i32,
+        name: String,
+    ) -> Result<MeasureSeries, sqlx::Error> {
+        let series = {
+            let mut tables = self.tables();
+            if !tables
+                .series
+                .filter(|s| s.name == name && s.id != id)
+                .is_empty()
+            {
+                return Err(duplicate("measure_series", &name));
+            }
+            tables.series.update(id, |s| s.name = name)?
+        };
+        self.notify(WSUpdate::MeasureSeriesList(vec![series.clone()]));
+        Ok(series)
+    }
+
+    async fn delete_measure_series(&self, id: i32) -> Result<(), sqlx::Error> {
+        let mut removed = Vec::new();
+        {
+            let mut tables = self.tables();
+            tables.series.get(id)?;
+            tables.remove_series(|s| s.id == id, &mut removed);
+        }
+        self.notify_removed(removed);
+        Ok(())
+    }
+
+    async fn list_arrows(&self, id: i32) -> Result<Vec<Arrow>, sqlx::Error> {
+        Ok(self.tables().arrows.filter(|a| a.bow_id == id))
+    }
+
+    async fn get_arrow(&self, id: i32) -> Result<Arrow, sqlx::Error> {
+        self.tables().arrows.get(id)
+    }
+
+    async fn add_arrow(&self, arrow: Arrow) -> Result<Arrow, sqlx::Error> {
+        let arrow = {
+            let mut tables = self.tables();
+            tables.bows.get(arrow.bow_id)?;
+            tables.arrows.insert(|id| Arrow { id, ..arrow })
+        };
+        self.notify(WSUpdate::ArrowList(vec![arrow.clone()]));
+        Ok(arrow)
+    }
+
+    async fn update_arrow(&self, arrow: Arrow) -> Result<Arrow, sqlx::Error> {
+        let arrow = {
+            let mut tables = self.tables();
+            tables.bows.get(arrow.bow_id)?;
+            tables.arrows.update(arrow.id, |a| *a = arrow.clone())?
+        };
+        self.notify(WSUpdate::ArrowList(vec![arrow.clone()]));
+        Ok(arrow)
+    }
+
+    async fn delete_arrow(&self, id: i32) -> Result<(), sqlx::Error> {
+        {
+            let mut tables = self.tables();
+            tables.arrows.get(id)?;
+            if !tables.measures.filter(|m| m.arrow_id == id).is_empty() {
+                return Err(sqlx::Error::Protocol(format!(
+                    "arrow {} is still referenced by measures",
+                    id
+                )));
+            }
+            tables.arrows.remove(|a| a.id == id);
+        }
+        self.notifier.notify_removed("arrow", vec![id]);
+        Ok(())
+    }
+
+    async fn list_measures(&self, id: i32) -> Result<Vec<Measure>, sqlx::Error> {
+        Ok(self.tables().measures.filter(|m| m.measure_series_id == id))
+    }
+
+    async fn get_measure(&self, id: i32) -> Result<Measure, sqlx::Error> {
+        self.tables().measures.get(id)
+    }
+
+    async fn add_measure(&self, measure: Measure) -> Result<Measure, sqlx::Error> {
+        let measure = {
+            let mut tables = self.tables();
+            tables.series.get(measure.measure_series_id)?;
+            tables.arrows.get(measure.arrow_id)?;
+            tables.measures.insert(|id| Measure { id, ..measure })
+        };
+        self.notify(WSUpdate::MeasureList(vec![measure.clone()]));
+        Ok(measure)
+    }
+
+    async fn delete_measure(&self, id: i32) -> Result<(), sqlx::Error> {
+        let mut removed = Vec::new();
+        {
+            let mut tables = self.tables();
+            tables.measures.get(id)?;
+            tables.remove_measures(|m| m.id == id, &mut removed);
+        }
+        self.notify_removed(removed);
+        Ok(())
+    }
+
+    async fn list_measure_points(&self, id: i32) -> Result<Vec<MeasurePoint>, sqlx::Error> {
+        let mut points = self.tables().points.filter(|p| p.measure_id == id);
+        points.sort_by_key(|p| (p.time, p.id));
+        Ok(points)
+    }
+
+    async fn add_measure_points(
+        &self,
+        measure_id: i32,
+        points: Vec<MeasurePoint>,
+    ) -> Result<Vec<MeasurePoint>, sqlx::Error> {
+        if points.is_empty() {
+            return Ok(points);
+        }
+        let points: Vec<MeasurePoint> = {
+            let mut tables = self.tables();
+            tables.measures.get(measure_id)?;
+            points
+                .into_iter()
+                .map(|point| {
+                    tables.points.insert(|id| MeasurePoint {
+                        id,
+                        measure_id,
+                        ..point
+                    })
+                })
+                .collect()
+        };
+        Ok(points)
+    }
+
+    async fn list_measure_results(&self, id: i32) -> Result<Vec<MeasureResult>, sqlx::Error> {
+        let tables = self.tables();
+        let measures: Vec<i32> = tables
+            .measures
+            .filter(|m| m.measure_series_id == id)
+            .iter()
+            .map(|m| m.id)
+            .collect();
+        Ok(tables.results.filter(|r| measures.contains(&r.measure_id)))
+    }
+
+    async fn add_measure_result(
+        &self,
+        result: MeasureResult,
+    ) -> Result<MeasureResult, sqlx::Error> {
+        let result = {
+            let mut tables = self.tables();
+            tables.measures.get(result.measure_id)?;
+            if !tables
+                .results
+                .filter(|r| r.measure_id == result.measure_id)
+                .is_empty()
+            {
+                return Err(sqlx::Error::Protocol(format!(
+                    "measure {} already has a result",
+                    result.measure_id
+                )));
+            }
+            tables.results.insert(|id| MeasureResult { id, ..result })
+        };
+        self.notify(WSUpdate::MeasureResultList(vec![result.clone()]));
+        Ok(result)
+    }
+
+    async fn get_user(&self, name: String) -> Result<User, sqlx::Error> {
+        self.tables()
+            .users
+            .filter(|u| u.name == name)
+            .pop()
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn add_user(&self, user: User) -> Result<User, sqlx::Error> {
+        let mut tables = self.tables();
+        if !tables.users.filter(|u| u.name == user.name).is_empty() {
+            return Err(duplicate("account", &user.name));
+        }
+        Ok(tables.users.insert(|id| User { id, ..user }))
+    }
+
+    async fn listener(self, channel: UnboundedSender<WSUpdate>) -> Result<(), sqlx::Error> {
+        self.notifier.listen(channel).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::OffsetDateTime;
+    use tokio::sync::mpsc;
+
+    fn bow(name: &str) -> Bow {
+        Bow {
+            id: -1,
+            name: name.into(),
+            max_draw_distance: 0.8,
+            remainder_arrow_length: 0.1,
+        }
+    }
+
+    #[tokio::test]
+    async fn listener_receives_inserts() {
+        let db = InMemoryArrowDB::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(db.clone().listener(tx));
+        tokio::task::yield_now().await;
+
+        let bow = db.add_bow(bow("bow")).await.unwrap();
+        assert_eq!(bow.id, 1);
+        assert!(matches!(rx.recv().await, Some(WSUpdate::BowList(b)) if b[0].id == 1));
+        assert!(db.add_bow(bow.clone()).await.is_err());
+    }
+
+    /// Adds a bow with one series, arrow and measure.
+    async fn measure(db: &InMemoryArrowDB) -> (Bow, MeasureSeries, Arrow, Measure) {
+        let bow = db.add_bow(bow("bow")).await.unwrap();
+        let series = db
+            .add_measure_series(MeasureSeries {
+                id: -1,
+                name: "series".into(),
+                rest_position: 0.2,
+                draw_distance: Some(0.6),
+                draw_force: None,
+                time: OffsetDateTime::now_utc(),
+                bow_id: bow.id,
+            })
+            .await
+            .unwrap();
+        let arrow = db
+            .add_arrow(Arrow {
+                id: -1,
+                name: None,
+                head_weight: None,
+                spline: None,
+                feather_length: None,
+                feather_type: None,
+                length: 0.7,
+                weight: 0.025,
+                bow_id: bow.id,
+            })
+            .await
+            .unwrap();
+        let measure = db
+            .add_measure(Measure {
+                id: -1,
+                measure_interval: 0.01,
+                measure_series_id: series.id,
+                arrow_id: arrow.id,
+            })
+            .await
+            .unwrap();
+        (bow, series, arrow, measure)
+    }
+
+    #[tokio::test]
+    async fn delete_cascades() {
+        let db = InMemoryArrowDB::new();
+        let (bow, series, arrow, measure) = measure(&db).await;
+        assert!(db.delete_arrow(arrow.id).await.is_err());
+
+        db.delete_bow(bow.id).await.unwrap();
+        assert!(db.list_bows().await.unwrap().is_empty());
+        assert!(db.list_measures(series.id).await.unwrap().is_empty());
+        assert!(matches!(
+            db.get_measure_series(series.id).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+        assert!(db.get_arrow(arrow.id).await.is_err());
+        assert!(db.list_measure_points(measure.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn points_are_inserted_in_one_batch() {
+        let db = InMemoryArrowDB::new();
+        let (_, _, _, measure) = measure(&db).await;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(db.clone().listener(tx));
+        tokio::task::yield_now().await;
+
+        let points = (0..3)
+            .map(|i| MeasurePoint {
+                id: -1,
+                time: i * 10_000,
+                draw_distance: 0.2 + i as f64 * 0.01,
+                force: i as f64,
+                measure_id: -1,
+            })
+            .collect();
+        let points = db.add_measure_points(measure.id, points).await.unwrap();
+        assert_eq!(points.iter().map(|p| p.id).collect::<Vec<_>>(), [1, 2, 3]);
+        assert!(points.iter().all(|p| p.measure_id == measure.id));
+        // the points are streamed live by the controller
+        tokio::task::yield_now().await;
+        assert!(rx.try_recv().is_err());
+        assert!(db.add_measure_points(-1, points).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn points_are_listed_in_time_order() {
+        let db = InMemoryArrowDB::new();
+        let (_, _, _, measure) = measure(&db).await;
+        let points = [20_000, 0, 10_000]
+            .iter()
+            .map(|&time| MeasurePoint {
+                id: -1,
+                time,
+                draw_distance: 0.2,
+                force: 0.0,
+                measure_id: -1,
+            })
+            .collect();
+        db.add_measure_points(measure.id, points).await.unwrap();
+        let points = db.list_measure_points(measure.id).await.unwrap();
+        assert_eq!(
+            points.iter().map(|p| (p.time, p.id)).collect::<Vec<_>>(),
+            [(0, 2), (10_000, 3), (20_000, 1)]
+        );
+    }
+}
@@ -1,6 +1,13 @@
 pub mod implementation;
+pub mod memory;
+pub mod notifier;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 pub mod traits;
 
 pub use self::traits::ArrowDB;
 //pub type ArrowDB = self::traits::ArrowDB + Clone;
 pub use implementation::PgArrowDB;
+pub use memory::InMemoryArrowDB;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteArrowDB;
@@ -0,0 +1,38 @@
+use crate::message::WSUpdate;
+
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Change notifications for databases running in process, replaces the
+/// `pg_notify` triggers of the postgres schema.
+#[derive(Clone, Default)]
+pub struct Notifier {
+    listeners: Arc<Mutex<Vec<UnboundedSender<WSUpdate>>>>,
+}
+
+impl Notifier {
+    /// Sends the update to all listeners, closed listeners are dropped.
+    pub fn notify(&self, update: WSUpdate) {
+        let mut listeners = self.listeners.lock().unwrap_or_else(|e| e.into_inner());
+        listeners.retain(|l| l.send(update.clone()).is_ok());
+    }
+
+    pub fn notify_removed(&self, table: &str, ids: Vec<i32>) {
+        if !ids.is_empty() {
+            self.notify(WSUpdate::Removed {
+                table: table.into(),
+                ids,
+            });
+        }
+    }
+
+    /// Registers the channel and waits until it is closed.
+    pub async fn listen(&self, channel: UnboundedSender<WSUpdate>) {
+        self.listeners
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(channel.clone());
+        channel.closed().await;
+    }
+}
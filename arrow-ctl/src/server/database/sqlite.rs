@@ -0,0 +1,756 @@
+use super::notifier::Notifier;
+use super::ArrowDB;
+use crate::config::DBConfiguration;
+use crate::message::WSUpdate;
+use crate::models::*;
+
+use async_trait::async_trait;
+
+use sqlx::sqlite::{Sqlite, SqliteConnectOptions, SqlitePool, SqliteQueryResult, SqliteRow};
+use sqlx::{Executor, FromRow, Row, Transaction};
+
+use time::OffsetDateTime;
+
+use tokio::sync::mpsc::UnboundedSender;
+
+const SCHEMA: &str = include_str!("../../../../db/sqlite/init_db.sql");
+
+/// Database stored in a single SQLite file, for running without postgres.
+#[derive(Clone)]
+pub struct SqliteArrowDB {
+    pool: SqlitePool,
+    notifier: Notifier,
+}
+
+impl SqliteArrowDB {
+    /// Opens the database file, creating it and the schema if missing.
+    pub async fn connect(config: &DBConfiguration) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::new()
+            .filename(&config.path)
+            .create_if_missing(true)
+            .foreign_keys(true);
+        Self::with_pool(SqlitePool::connect_with(options).await?).await
+    }
+
+    async fn with_pool(pool: SqlitePool) -> Result<Self, sqlx::Error> {
+        pool.execute(SCHEMA).await?;
+        Ok(Self {
+            pool,
+            notifier: Notifier::default(),
+        })
+    }
+}
+
+/// Ids returned by a query with a single id parameter, used to collect the
+/// rows removed by cascade before deleting them in the same transaction.
+async fn ids(
+    tx: &mut Transaction<'_, Sqlite>,
+    query: &str,
+    id: i32,
+) -> Result<Vec<i32>, sqlx::Error> {
+    sqlx::query_scalar(query).bind(id).fetch_all(tx).await
+}
+
+async fn delete(tx: &mut Transaction<'_, Sqlite>, table: &str, id: i32) -> Result<(), sqlx::Error> {
+    let query = format!("DELETE FROM {} WHERE id = ?", table);
+    let result = sqlx::query(&query).bind(id).execute(tx).await?;
+    found(&result)
+}
+
+fn found(result: &SqliteQueryResult) -> Result<(), sqlx::Error> {
+    match result.rows_affected() {
+        0 => Err(sqlx::Error::RowNotFound),
+        _ => Ok(()),
+    }
+}
+
+fn timestamp(time: OffsetDateTime) -> i64 {
+    (time.unix_timestamp_nanos() / 1000) as i64
+}
+
+impl<'r> FromRow<'r, SqliteRow> for Bow {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            name: row.try_get("name")?,
+            max_draw_distance: row.try_get("max_draw_distance")?,
+            remainder_arrow_length: row.try_get("remainder_arrow_length")?,
+        })
+    }
+}
+
+impl<'r> FromRow<'r, SqliteRow> for MeasureSeries {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        let micros: i64 = row.try_get("time")?;
+        Ok(Self {
+            id: row.try_get("id")?,
+            name: row.try_get("name")?,
+            rest_position: row.try_get("rest_position")?,
+            draw_distance: row.try_get("draw_distance")?,
+            draw_force: row.try_get("draw_force")?,
+            time: OffsetDateTime::from_unix_timestamp_nanos(micros as i128 * 1000),
+            bow_id: row.try_get("bow_id")?,
+        })
+    }
+}
+
+impl<'r> FromRow<'r, SqliteRow> for Arrow {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            name: row.try_get("name")?,
+            head_weight: row.try_get("head_weight")?,
+            spline: row.try_get("spline")?,
+            feather_length: row.try_get("feather_length")?,
+            feather_type: row.try_get("feather_type")?,
+            length: row.try_get("length")?,
+            weight: row.try_get("weight")?,
+            bow_id: row.try_get("bow_id")?,
+        })
+    }
+}
+
+impl<'r> FromRow<'r, SqliteRow> for Measure {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            measure_interval: row.try_get("measure_interval")?,
+            measure_series_id: row.try_get("measure_series_id")?,
+            arrow_id: row.try_get("arrow_id")?,
+        })
+    }
+}
+
+impl<'r> FromRow<'r, SqliteRow> for MeasurePoint {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            time: row.try_get("time")?,
+            draw_distance: row.try_get("draw_distance")?,
+            force: row.try_get("force")?,
+            measure_id: row.try_get("measure_id")?,
+        })
+    }
+}
+
+impl<'r> FromRow<'r, SqliteRow> for MeasureResult {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            measure_id: row.try_get("measure_id")?,
+            arrow_speed: row.try_get("arrow_speed")?,
+            potential_energy: row.try_get("potential_energy")?,
+            kinetic_energy: row.try_get("kinetic_energy")?,
+            efficiency: row.try_get("efficiency")?,
+            wasted_energy: row.try_get("wasted_energy")?,
+        })
+    }
+}
+
+impl<'r> FromRow<'r, SqliteRow> for User {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        let role: String = row.try_get("role")?;
+        Ok(Self {
+            id: row.try_get("id")?,
+            name: row.try_get("name")?,
+            password_hash: row.try_get("password_hash")?,
+            role: role
+                .parse()
+                .map_err(|e: String| sqlx::Error::Decode(e.into()))?,
+        })
+    }
+}
+
+#[async_trait]
+impl ArrowDB for SqliteArrowDB {
+    async fn list_bows(&self) -> Result<Vec<Bow>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM bow")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn get_bow(&self, id: i32) -> Result<Bow, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM bow WHERE id = ?")
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn add_bow(&self, bow: Bow) -> Result<Bow, sqlx::Error> {
+        let rec = sqlx::query(
+            r#"INSERT INTO bow
+            (name, max_draw_distance, remainder_arrow_length)
+            VALUES (?, ?, ?)"#,
+        )
+        .bind(&bow.name)
+        .bind(bow.max_draw_distance)
+        .bind(bow.remainder_arrow_length)
+        .execute(&self.pool)
+        .await?;
+        let bow = Bow {
+            id: rec.last_insert_rowid() as i32,
+            ..bow
+        };
+        self.notifier.notify(WSUpdate::BowList(vec![bow.clone()]));
+        Ok(bow)
+    }
+
+    async fn update_bow(&self, bow: Bow) -> Result<Bow, sqlx::Error> {
+        let rec = sqlx::query(
+            r#"UPDATE bow
+            SET name = ?, max_draw_distance = ?, remainder_arrow_length = ?
+            WHERE id = ?"#,
+        )
+        .bind(&bow.name)
+        .bind(bow.max_draw_distance)
+        .bind(bow.remainder_arrow_length)
+        .bind(bow.id)
+        .execute(&self.pool)
+        .await?;
+        found(&rec)?;
+        let bow = self.get_bow(bow.id).await?;
+        self.notifier.notify(WSUpdate::BowList(vec![bow.clone()]));
+        Ok(bow)
+    }
+
+    async fn delete_bow(&self, id: i32) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let results = ids(
+            &mut tx,
+            r#"SELECT measure_result.id FROM measure_result
+                JOIN measure ON measure.id = measure_result.measure_id
+                JOIN measure_series ON measure_series.id = measure.measure_series_id
+                WHERE measure_series.bow_id = ?"#,
+            id,
+        )
+        .await?;
+        let measures = ids(
+            &mut tx,
+            r#"SELECT measure.id FROM measure
+                JOIN measure_series ON measure_series.id = measure.measure_series_id
+                WHERE measure_series.bow_id = ?"#,
+            id,
+        )
+        .await?;
+        let series = ids(
+            &mut tx,
+            "SELECT id FROM measure_series WHERE bow_id = ?",
+            id,
+        )
+        .await?;
+        let arrows = ids(&mut tx, "SELECT id FROM arrow WHERE bow_id = ?", id).await?;
+        delete(&mut tx, "bow", id).await?;
+        tx.commit().await?;
+        self.notifier.notify_removed("measure_result", results);
+        self.notifier.notify_removed("measure", measures);
+        self.notifier.notify_removed("measure_series", series);
+        self.notifier.notify_removed("arrow", arrows);
+        self.notifier.notify_removed("bow", vec![id]);
+        Ok(())
+    }
+
+    async fn list_measurement_series(&self, id: i32) -> Result<Vec<MeasureSeries>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM measure_series WHERE bow_id = ?")
+            .bind(id)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn get_measure_series(&self, id: i32) -> Result<MeasureSeries, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM measure_series WHERE id = ?")
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn add_measure_series(
+        &self,
+        series: MeasureSeries,
+    ) -> Result<MeasureSeries, sqlx::Error> {
+        let rec = sqlx::query(
+            r#"INSERT INTO measure_series
+            (name, rest_position, draw_distance, draw_force, time, bow_id)
+            VALUES (?, ?, ?, ?, ?, ?)"#,
+        )
+        .bind(&series.name)
+        .bind(series.rest_position)
+        .bind(series.draw_distance)
+        .bind(series.draw_force)
+        .bind(timestamp(series.time))
+        .bind(series.bow_id)
+        .execute(&self.pool)
+        .await?;
+        let series = MeasureSeries {
+            id: rec.last_insert_rowid() as i32,
+            ..series
+        };
+        self.notifier
+            .notify(WSUpdate::MeasureSeriesList(vec![series.clone()]));
+        Ok(series)
+    }
+
+    async fn rename_measure_series(
+        &self,
+        id: i32,
+        name: String,
+    ) -> Result<MeasureSeries, sqlx::Error> {
+        let rec = sqlx::query("UPDATE measure_series SET name = ? WHERE id = ?")
+            .bind(name)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        found(&rec)?;
+        let series = self.get_measure_series(id).await?;
+        self.notifier
+            .notify(WSUpdate::MeasureSeriesList(vec![series.clone()]));
+        Ok(series)
+    }
+
+    async fn delete_measure_series(&self, id: i32) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let results = ids(
+            &mut tx,
+            r#"SELECT measure_result.id FROM measure_result
+                JOIN measure ON measure.id = measure_result.measure_id
+                WHERE measure.measure_series_id = ?"#,
+            id,
+        )
+        .await?;
+        let measures = ids(
+            &mut tx,
+            "SELECT id FROM measure WHERE measure_series_id = ?",
+            id,
+        )
+        .await?;
+        delete(&mut tx, "measure_series", id).await?;
+        tx.commit().await?;
+        self.notifier.notify_removed("measure_result", results);
+        self.notifier.notify_removed("measure", measures);
+        self.notifier.notify_removed("measure_series", vec![id]);
+        Ok(())
+    }
+
+    async fn list_arrows(&self, id: i32) -> Result<Vec<Arrow>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM arrow WHERE bow_id = ?")
+            .bind(id)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn get_arrow(&self, id: i32) -> Result<Arrow, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM arrow WHERE id = ?")
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn add_arrow(&self, arrow: Arrow) -> Result<Arrow, sqlx::Error> {
+        let rec = sqlx::query(
+            r#"INSERT INTO arrow
+            (name, head_weight, spline, feather_length, feather_type, length, weight, bow_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)"#,
+        )
+        .bind(&arrow.name)
+        .bind(arrow.head_weight)
+        .bind(arrow.spline)
+        .bind(arrow.feather_length)
+        .bind(&arrow.feather_type)
+        .bind(arrow.length)
+        .bind(arrow.weight)
+        .bind(arrow.bow_id)
+        .execute(&self.pool)
+        .await?;
+        let arrow = Arrow {
+            id: rec.last_insert_rowid() as i32,
+            ..arrow
+        };
+        self.notifier
+            .notify(WSUpdate::ArrowList(vec![arrow.clone()]));
+        Ok(arrow)
+    }
+
+    async fn update_arrow(&self, arrow: Arrow) -> Result<Arrow, sqlx::Error> {
+        let rec = sqlx::query(
+            r#"UPDATE arrow
+            SET name = ?, head_weight = ?, spline = ?, feather_length = ?,
+            feather_type = ?, length = ?, weight = ?, bow_id = ?
+            WHERE id = ?"#,
+        )
+        .bind(&arrow.name)
+        .bind(arrow.head_weight)
+        .bind(arrow.spline)
+        .bind(arrow.feather_length)
+        .bind(&arrow.feather_type)
+        .bind(arrow.length)
+        .bind(arrow.weight)
+        .bind(arrow.bow_id)
+        .bind(arrow.id)
+        .execute(&self.pool)
+        .await?;
+        found(&rec)?;
+        let arrow = self.get_arrow(arrow.id).await?;
+        self.notifier
+            .notify(WSUpdate::ArrowList(vec![arrow.clone()]));
+        Ok(arrow)
+    }
+
+    async fn delete_arrow(&self, id: i32) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        delete(&mut tx, "arrow", id).await?;
+        tx.commit().await?;
+        self.notifier.notify_removed("arrow", vec![id]);
+        Ok(())
+    }
+
+    async fn list_measures(&self, id: i32) -> Result<Vec<Measure>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM measure WHERE measure_series_id = ?")
+            .bind(id)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn get_measure(&self, id: i32) -> Result<Measure, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM measure WHERE id = ?")
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn add_measure(&self, measure: Measure) -> Result<Measure, sqlx::Error> {
+        let rec = sqlx::query(
+            r#"INSERT INTO measure
+            (measure_interval, measure_series_id, arrow_id)
+            VALUES (?, ?, ?)"#,
+        )
+        .bind(measure.measure_interval)
+        .bind(measure.measure_series_id)
+        .bind(measure.arrow_id)
+        .execute(&self.pool)
+        .await?;
+        let measure = Measure {
+            id: rec.last_insert_rowid() as i32,
+            ..measure
+        };
+        self.notifier
+            .notify(WSUpdate::MeasureList(vec![measure.clone()]));
+        Ok(measure)
+    }
+
+    async fn delete_measure(&self, id: i32) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let results = ids(
+            &mut tx,
+            "SELECT id FROM measure_result WHERE measure_id = ?",
+            id,
+        )
+        .await?;
+        delete(&mut tx, "measure", id).await?;
+        tx.commit().await?;
+        self.notifier.notify_removed("measure_result", results);
+        self.notifier.notify_removed("measure", vec![id]);
+        Ok(())
+    }
+
+    async fn list_measure_points(&self, id: i32) -> Result<Vec<MeasurePoint>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM measure_point WHERE measure_id = ? ORDER BY time, id")
+            .bind(id)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn add_measure_points(
+        &self,
+        measure_id: i32,
+        points: Vec<MeasurePoint>,
+    ) -> Result<Vec<MeasurePoint>, sqlx::Error> {
+        if points.is_empty() {
+            return Ok(points);
+        }
+        let mut tx = self.pool.begin().await?;
+        let mut stored = Vec::with_capacity(points.len());
+        for point in points {
+            let rec = sqlx::query(
+                r#"INSERT INTO measure_point
+                (time, draw_distance, force, measure_id)
+                VALUES (?, ?, ?, ?)"#,
+            )
+            .bind(point.time)
+            .bind(point.draw_distance)
+            .bind(point.force)
+            .bind(measure_id)
+            .execute(&mut tx)
+            .await?;
+            stored.push(MeasurePoint {
+                id: rec.last_insert_rowid() as i32,
+                measure_id,
+                ..point
+            });
+        }
+        tx.commit().await?;
+        Ok(stored)
+    }
+
+    async fn list_measure_results(&self, id: i32) -> Result<Vec<MeasureResult>, sqlx::Error> {
+        sqlx::query_as(
+            r#"SELECT measure_result.* FROM measure_result
+            JOIN measure ON measure.id = measure_result.measure_id
+            WHERE measure.measure_series_id = ?"#,
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn add_measure_result(
+        &self,
+        result: MeasureResult,
+    ) -> Result<MeasureResult, sqlx::Error> {
+        let rec = sqlx::query(
+            r#"INSERT INTO measure_result
+            (measure_id, arrow_speed, potential_energy, kinetic_energy, efficiency, wasted_energy)
+            VALUES (?, ?, ?, ?, ?, ?)"#,
+        )
+        .bind(result.measure_id)
+        .bind(result.arrow_speed)
+        .bind(result.potential_energy)
+        .bind(result.kinetic_energy)
+        .bind(result.efficiency)
+        .bind(result.wasted_energy)
+        .execute(&self.pool)
+        .await?;
+        let result = MeasureResult {
+            id: rec.last_insert_rowid() as i32,
+            ..result
+        };
+        self.notifier
+            .notify(WSUpdate::MeasureResultList(vec![result.clone()]));
+        Ok(result)
+    }
+
+    async fn get_user(&self, name: String) -> Result<User, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM account WHERE name = ?")
+            .bind(name)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn add_user(&self, user: User) -> Result<User, sqlx::Error> {
+        let rec = sqlx::query("INSERT INTO account (name, password_hash, role) VALUES (?, ?, ?)")
+            .bind(&user.name)
+            .bind(&user.password_hash)
+            .bind(user.role.as_str())
+            .execute(&self.pool)
+            .await?;
+        Ok(User {
+            id: rec.last_insert_rowid() as i32,
+            ..user
+        })
+    }
+
+    async fn listener(self, channel: UnboundedSender<WSUpdate>) -> Result<(), sqlx::Error> {
+        self.notifier.listen(channel).await;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use std::str::FromStr;
+    use tokio::sync::mpsc;
+
+    /// Database in memory, limited to one connection as every connection
+    /// opens a database of its own.
+    async fn db() -> SqliteArrowDB {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:")
+            .unwrap()
+            .foreign_keys(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await
+            .unwrap();
+        SqliteArrowDB::with_pool(pool).await.unwrap()
+    }
+
+    fn bow(name: &str) -> Bow {
+        Bow {
+            id: -1,
+            name: name.into(),
+            max_draw_distance: 0.8,
+            remainder_arrow_length: 0.1,
+        }
+    }
+
+    /// Adds a bow with one series, arrow and measure.
+    async fn measure(db: &SqliteArrowDB) -> (Bow, MeasureSeries, Arrow, Measure) {
+        let bow = db.add_bow(bow("bow")).await.unwrap();
+        let series = db
+            .add_measure_series(MeasureSeries {
+                id: -1,
+                name: "series".into(),
+                rest_position: 0.2,
+                draw_distance: Some(0.6),
+                draw_force: None,
+                time: OffsetDateTime::from_unix_timestamp_nanos(1_600_000_000_123_456_000),
+                bow_id: bow.id,
+            })
+            .await
+            .unwrap();
+        let arrow = db
+            .add_arrow(Arrow {
+                id: -1,
+                name: Some("arrow".into()),
+                head_weight: None,
+                spline: None,
+                feather_length: None,
+                feather_type: None,
+                length: 0.7,
+                weight: 0.025,
+                bow_id: bow.id,
+            })
+            .await
+            .unwrap();
+        let measure = db
+            .add_measure(Measure {
+                id: -1,
+                measure_interval: 0.01,
+                measure_series_id: series.id,
+                arrow_id: arrow.id,
+            })
+            .await
+            .unwrap();
+        (bow, series, arrow, measure)
+    }
+
+    #[tokio::test]
+    async fn rows_are_stored_and_updated() {
+        let db = db().await;
+        let (bow, series, arrow, measure) = measure(&db).await;
+        assert_eq!(db.get_bow(bow.id).await.unwrap().name, "bow");
+        assert!(db.add_bow(bow.clone()).await.is_err());
+        assert_eq!(db.list_arrows(bow.id).await.unwrap().len(), 1);
+        assert_eq!(db.list_measures(series.id).await.unwrap()[0].id, measure.id);
+
+        let renamed = db
+            .update_bow(Bow {
+                name: "renamed".into(),
+                ..bow.clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(renamed.name, "renamed");
+        assert_eq!(db.list_bows().await.unwrap()[0].name, "renamed");
+        let weight = db
+            .update_arrow(Arrow {
+                weight: 0.03,
+                ..arrow.clone()
+            })
+            .await
+            .unwrap()
+            .weight;
+        assert!((weight - 0.03).abs() < 1e-6);
+        let series = db
+            .rename_measure_series(series.id, "renamed".into())
+            .await
+            .unwrap();
+        assert_eq!(series.name, "renamed");
+
+        assert!(matches!(
+            db.update_bow(Bow { id: 99, ..bow }).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+        assert!(matches!(
+            db.update_arrow(Arrow { id: 99, ..arrow }).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+        assert!(matches!(
+            db.get_measure_series(99).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn series_time_keeps_microseconds() {
+        let db = db().await;
+        let (_, series, _, _) = measure(&db).await;
+        let stored = db.get_measure_series(series.id).await.unwrap();
+        assert_eq!(stored.time, series.time);
+        assert_eq!(stored.time.nanosecond(), 123_456_000);
+    }
+
+    #[tokio::test]
+    async fn delete_cascades() {
+        let db = db().await;
+        let (bow, series, arrow, measure) = measure(&db).await;
+        assert!(db.delete_arrow(arrow.id).await.is_err());
+        db.add_measure_result(MeasureResult {
+            id: -1,
+            measure_id: measure.id,
+            arrow_speed: 50.0,
+            potential_energy: 40.0,
+            kinetic_energy: 30.0,
+            efficiency: 0.75,
+            wasted_energy: 10.0,
+        })
+        .await
+        .unwrap();
+
+        db.delete_bow(bow.id).await.unwrap();
+        assert!(db.list_bows().await.unwrap().is_empty());
+        assert!(db.list_measures(series.id).await.unwrap().is_empty());
+        assert!(db.list_measure_results(series.id).await.unwrap().is_empty());
+        assert!(matches!(
+            db.get_arrow(arrow.id).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+        assert!(db.list_measure_points(measure.id).await.unwrap().is_empty());
+        assert!(matches!(
+            db.delete_bow(bow.id).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn points_are_stored_in_time_order() {
+        let db = db().await;
+        let (_, _, _, measure) = measure(&db).await;
+        let points = [20_000, 0, 10_000]
+            .iter()
+            .map(|&time| MeasurePoint {
+                id: -1,
+                time,
+                draw_distance: 0.2,
+                force: 1.5,
+                measure_id: -1,
+            })
+            .collect();
+        let stored = db.add_measure_points(measure.id, points).await.unwrap();
+        assert_eq!(stored.iter().map(|p| p.id).collect::<Vec<_>>(), [1, 2, 3]);
+        assert!(stored.iter().all(|p| p.measure_id == measure.id));
+
+        let points = db.list_measure_points(measure.id).await.unwrap();
+        assert_eq!(
+            points.iter().map(|p| (p.time, p.id)).collect::<Vec<_>>(),
+            [(0, 2), (10_000, 3), (20_000, 1)]
+        );
+        assert!(db.add_measure_points(99, points).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn listener_receives_changes() {
+        let db = db().await;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(db.clone().listener(tx));
+        tokio::task::yield_now().await;
+
+        let bow = db.add_bow(bow("bow")).await.unwrap();
+        assert!(matches!(rx.recv().await, Some(WSUpdate::BowList(b)) if b[0].id == bow.id));
+        db.delete_bow(bow.id).await.unwrap();
+        assert!(matches!(
+            rx.recv().await,
+            Some(WSUpdate::Removed { table, ids }) if table == "bow" && ids == [bow.id]
+        ));
+    }
+}
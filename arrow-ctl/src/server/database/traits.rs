@@ -6,19 +6,51 @@ use tokio::sync::mpsc::UnboundedSender;
 #[async_trait]
 pub trait ArrowDB: Clone + Send + Sync {
     async fn list_bows(&self) -> Result<Vec<Bow>, sqlx::Error>;
+    async fn get_bow(&self, id: i32) -> Result<Bow, sqlx::Error>;
     async fn add_bow(&self, bow: Bow) -> Result<Bow, sqlx::Error>;
+    async fn update_bow(&self, bow: Bow) -> Result<Bow, sqlx::Error>;
+    async fn delete_bow(&self, id: i32) -> Result<(), sqlx::Error>;
 
     async fn list_measurement_series(&self, id: i32) -> Result<Vec<MeasureSeries>, sqlx::Error>;
+    async fn get_measure_series(&self, id: i32) -> Result<MeasureSeries, sqlx::Error>;
     async fn add_measure_series(&self, series: MeasureSeries)
         -> Result<MeasureSeries, sqlx::Error>;
+    async fn rename_measure_series(
+        &self,
+        id: i32,
+        name: String,
+    ) -> Result<MeasureSeries, sqlx::Error>;
+    async fn delete_measure_series(&self, id: i32) -> Result<(), sqlx::Error>;
 
     async fn list_arrows(&self, id: i32) -> Result<Vec<Arrow>, sqlx::Error>;
+    async fn get_arrow(&self, id: i32) -> Result<Arrow, sqlx::Error>;
     async fn add_arrow(&self, arrow: Arrow) -> Result<Arrow, sqlx::Error>;
+    async fn update_arrow(&self, arrow: Arrow) -> Result<Arrow, sqlx::Error>;
+    async fn delete_arrow(&self, id: i32) -> Result<(), sqlx::Error>;
 
     async fn list_measures(&self, id: i32) -> Result<Vec<Measure>, sqlx::Error>;
+    async fn get_measure(&self, id: i32) -> Result<Measure, sqlx::Error>;
     async fn add_measure(&self, measure: Measure) -> Result<Measure, sqlx::Error>;
+    async fn delete_measure(&self, id: i32) -> Result<(), sqlx::Error>;
 
     async fn list_measure_points(&self, id: i32) -> Result<Vec<MeasurePoint>, sqlx::Error>;
+    /// Stores a batch of points of one measure in a single statement. No
+    /// change notification is sent, the controller streams the points live.
+    /// Points stored otherwise, e.g. by an archive import, reach the clients
+    /// only when they list the points of the measure.
+    async fn add_measure_points(
+        &self,
+        measure_id: i32,
+        points: Vec<MeasurePoint>,
+    ) -> Result<Vec<MeasurePoint>, sqlx::Error>;
+
+    async fn list_measure_results(&self, id: i32) -> Result<Vec<MeasureResult>, sqlx::Error>;
+    async fn add_measure_result(&self, result: MeasureResult)
+        -> Result<MeasureResult, sqlx::Error>;
+
+    /// Users are looked up by their unique name.
+    async fn get_user(&self, name: String) -> Result<User, sqlx::Error>;
+    async fn add_user(&self, user: User) -> Result<User, sqlx::Error>;
 
     async fn listener(self, channel: UnboundedSender<WSUpdate>) -> Result<(), sqlx::Error>;
 }
@@ -29,18 +61,36 @@ mockall::mock! {
     #[async_trait]
     impl ArrowDB for DB {
     async fn list_bows(&self) -> Result<Vec<Bow>, sqlx::Error>;
+    async fn get_bow(&self, id: i32) -> Result<Bow, sqlx::Error>;
     async fn add_bow(&self, bow: Bow) -> Result<Bow, sqlx::Error>;
+    async fn update_bow(&self, bow: Bow) -> Result<Bow, sqlx::Error>;
+    async fn delete_bow(&self, id: i32) -> Result<(), sqlx::Error>;
 
     async fn list_measurement_series(&self, id: i32) -> Result<Vec<MeasureSeries>, sqlx::Error>;
+    async fn get_measure_series(&self, id: i32) -> Result<MeasureSeries, sqlx::Error>;
     async fn add_measure_series(&self, series: MeasureSeries) -> Result<MeasureSeries, sqlx::Error>;
+    async fn rename_measure_series(&self, id: i32, name: String) -> Result<MeasureSeries, sqlx::Error>;
+    async fn delete_measure_series(&self, id: i32) -> Result<(), sqlx::Error>;
 
     async fn list_arrows(&self, id: i32) -> Result<Vec<Arrow>, sqlx::Error>;
+    async fn get_arrow(&self, id: i32) -> Result<Arrow, sqlx::Error>;
     async fn add_arrow(&self, arrow: Arrow) -> Result<Arrow, sqlx::Error>;
+    async fn update_arrow(&self, arrow: Arrow) -> Result<Arrow, sqlx::Error>;
+    async fn delete_arrow(&self, id: i32) -> Result<(), sqlx::Error>;
 
     async fn list_measures(&self, id: i32) -> Result<Vec<Measure>, sqlx::Error>;
+    async fn get_measure(&self, id: i32) -> Result<Measure, sqlx::Error>;
     async fn add_measure(&self, measure: Measure) -> Result<Measure, sqlx::Error>;
+    async fn delete_measure(&self, id: i32) -> Result<(), sqlx::Error>;
 
     async fn list_measure_points(&self, id: i32) -> Result<Vec<MeasurePoint>, sqlx::Error>;
+    async fn add_measure_points(&self, measure_id: i32, points: Vec<MeasurePoint>) -> Result<Vec<MeasurePoint>, sqlx::Error>;
+
+    async fn list_measure_results(&self, id: i32) -> Result<Vec<MeasureResult>, sqlx::Error>;
+    async fn add_measure_result(&self, result: MeasureResult) -> Result<MeasureResult, sqlx::Error>;
+
+    async fn get_user(&self, name: String) -> Result<User, sqlx::Error>;
+    async fn add_user(&self, user: User) -> Result<User, sqlx::Error>;
     async fn listener(self, channel: UnboundedSender<WSUpdate>) -> Result<(), sqlx::Error>;
     }
     impl Clone for DB {
@@ -0,0 +1,281 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::error;
+use mime_guess::mime::{self, Mime};
+use percent_encoding::percent_decode_str;
+
+use std::fs::Metadata;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use warp::filters::BoxedFilter;
+use warp::http::{header, StatusCode};
+use warp::hyper::Body;
+use warp::path::Tail;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+/// Files below this directory have content hashes in their names and never
+/// change, all others are revalidated on every use.
+const ASSET_DIR: &str = "assets";
+const CACHE_ASSET: &str = "public, max-age=31536000, immutable";
+const CACHE_REVALIDATE: &str = "no-cache";
+
+/// Serves the built web front-end from `dir`. Paths without file extension
+/// which do not exist get `index.html`, so the client side router can handle
+/// them. Clients accepting gzip get the `.gz` file next to the requested one
+/// if there is one, text files without are compressed on the fly.
+pub fn routes(dir: Option<PathBuf>) -> BoxedFilter<(Response,)> {
+    let dir = match dir {
+        Some(dir) => dir,
+        None => {
+            return warp::any()
+                .and_then(|| async { Err::<Response, _>(warp::reject::not_found()) })
+                .boxed()
+        }
+    };
+    warp::get()
+        .and(warp::path::tail())
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and_then(move |tail: Tail, encoding, if_none_match| {
+            serve(dir.clone(), tail, encoding, if_none_match)
+        })
+        .boxed()
+}
+
+async fn serve(
+    dir: PathBuf,
+    tail: Tail,
+    encoding: Option<String>,
+    if_none_match: Option<String>,
+) -> Result<Response, warp::Rejection> {
+    let path = tail.as_str();
+    if path == "api" || path.starts_with("api/") || path.starts_with("ws/") {
+        return Err(warp::reject::not_found());
+    }
+    let relative = sanitize(path).ok_or_else(warp::reject::not_found)?;
+    let mut file = dir.join(&relative);
+    if !is_file(&file).await {
+        // missing assets must not be answered with the page
+        if relative.extension().is_some() {
+            return Err(warp::reject::not_found());
+        }
+        file = dir.join("index.html");
+    }
+
+    let mime = mime_guess::from_path(&file).first_or_octet_stream();
+    let mut source = file.clone();
+    let mut gzip = false;
+    let mut compress = false;
+    if matches!(encoding, Some(e) if accepts_gzip(&e)) {
+        let mut compressed = file.clone().into_os_string();
+        compressed.push(".gz");
+        if is_file(Path::new(&compressed)).await {
+            source = compressed.into();
+        } else {
+            compress = is_compressible(&mime);
+        }
+        gzip = source != file || compress;
+    }
+    let metadata = tokio::fs::metadata(&source)
+        .await
+        .map_err(|_| warp::reject::not_found())?;
+    let etag = etag(&metadata, gzip);
+    let cache = if matches!(file.strip_prefix(&dir), Ok(f) if f.starts_with(ASSET_DIR)) {
+        CACHE_ASSET
+    } else {
+        CACHE_REVALIDATE
+    };
+
+    let builder = warp::http::Response::builder()
+        .header(header::CACHE_CONTROL, cache)
+        .header(header::ETAG, &etag)
+        .header(header::VARY, "accept-encoding");
+    if matches!(if_none_match, Some(tags) if matches_etag(&tags, &etag)) {
+        return Ok(builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .into_response());
+    }
+    let content = match tokio::fs::read(&source).await {
+        Ok(content) if compress => gzip_compress(&content),
+        result => result,
+    };
+    let content = match content {
+        Ok(content) => content,
+        Err(e) => {
+            error!(target: "arrow::web", "cannot read '{}': '{}'", source.display(), e);
+            return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+    let builder = builder.header(header::CONTENT_TYPE, mime.as_ref());
+    let builder = if gzip {
+        builder.header(header::CONTENT_ENCODING, "gzip")
+    } else {
+        builder
+    };
+    Ok(builder.body(Body::from(content)).into_response())
+}
+
+/// Decodes the request path, refusing segments which could leave the
+/// directory or reveal hidden files.
+fn sanitize(path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+    let mut relative = PathBuf::new();
+    for segment in decoded.split('/').filter(|s| !s.is_empty()) {
+        if segment.starts_with('.') || segment.contains('\\') {
+            return None;
+        }
+        relative.push(segment);
+    }
+    Some(relative)
+}
+
+async fn is_file(path: &Path) -> bool {
+    matches!(tokio::fs::metadata(path).await, Ok(m) if m.is_file())
+}
+
+fn accepts_gzip(encoding: &str) -> bool {
+    encoding.split(',').any(|coding| {
+        let mut params = coding.split(';').map(str::trim);
+        params.next() == Some("gzip") && !params.any(|p| p == "q=0")
+    })
+}
+
+/// Text formats shrink considerably, images and fonts are compressed already.
+fn is_compressible(mime: &Mime) -> bool {
+    mime.type_() == mime::TEXT
+        || matches!(
+            mime.subtype().as_str(),
+            "javascript" | "json" | "xml" | "wasm"
+        )
+        || matches!(mime.suffix(), Some(s) if s == mime::XML || s == mime::JSON)
+}
+
+fn gzip_compress(content: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content)?;
+    encoder.finish()
+}
+
+fn etag(metadata: &Metadata, gzip: bool) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |m| m.as_secs());
+    let encoding = if gzip { "-gz" } else { "" };
+    format!("\"{:x}-{:x}{}\"", metadata.len(), modified, encoding)
+}
+
+fn matches_etag(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag == etag || tag.strip_prefix("W/") == Some(etag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn frontend(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("arrow-frontend-{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join(ASSET_DIR)).unwrap();
+        std::fs::write(dir.join("index.html"), "<html></html>").unwrap();
+        std::fs::write(dir.join(ASSET_DIR).join("app.1234.js"), "app()").unwrap();
+        std::fs::write(dir.join(ASSET_DIR).join("app.1234.js.gz"), "gzipped").unwrap();
+        dir
+    }
+
+    #[test]
+    fn paths_stay_in_directory() {
+        assert_eq!(sanitize("assets/a%20b.js"), Some("assets/a b.js".into()));
+        assert_eq!(sanitize(""), Some(PathBuf::new()));
+        assert_eq!(sanitize("assets/../../etc/passwd"), None);
+        assert_eq!(sanitize("%2e%2e/etc/passwd"), None);
+        assert_eq!(sanitize(".env"), None);
+    }
+
+    #[tokio::test]
+    async fn pages_fall_back_to_index() {
+        let routes = routes(Some(frontend("pages")));
+
+        let reply = warp::test::request().path("/bows/3").reply(&routes).await;
+        assert_eq!(reply.status(), StatusCode::OK);
+        assert_eq!(reply.body(), "<html></html>");
+        assert_eq!(reply.headers()[header::CONTENT_TYPE], "text/html");
+        assert_eq!(reply.headers()[header::CACHE_CONTROL], CACHE_REVALIDATE);
+
+        let etag = reply.headers()[header::ETAG].clone();
+        let reply = warp::test::request()
+            .path("/")
+            .header("if-none-match", etag)
+            .reply(&routes)
+            .await;
+        assert_eq!(reply.status(), StatusCode::NOT_MODIFIED);
+
+        for path in ["/assets/missing.js", "/api/unknown"] {
+            let reply = warp::test::request().path(path).reply(&routes).await;
+            assert_eq!(reply.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn assets_are_sent_compressed() {
+        let routes = routes(Some(frontend("assets")));
+
+        let reply = warp::test::request()
+            .path("/assets/app.1234.js")
+            .header("accept-encoding", "gzip, deflate")
+            .reply(&routes)
+            .await;
+        assert_eq!(reply.body(), "gzipped");
+        assert_eq!(reply.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(reply.headers()[header::CACHE_CONTROL], CACHE_ASSET);
+        assert!(reply.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .contains("javascript"));
+
+        let reply = warp::test::request()
+            .path("/assets/app.1234.js")
+            .header("accept-encoding", "gzip;q=0")
+            .reply(&routes)
+            .await;
+        assert_eq!(reply.body(), "app()");
+        assert!(reply.headers().get(header::CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn text_is_compressed_on_the_fly() {
+        let dir = frontend("on-the-fly");
+        std::fs::write(dir.join(ASSET_DIR).join("logo.png"), "png").unwrap();
+        let routes = routes(Some(dir));
+
+        let reply = warp::test::request()
+            .path("/")
+            .header("accept-encoding", "gzip")
+            .reply(&routes)
+            .await;
+        assert_eq!(reply.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(reply.headers()[header::CONTENT_TYPE], "text/html");
+        let mut page = String::new();
+        flate2::read::GzDecoder::new(&reply.body()[..])
+            .read_to_string(&mut page)
+            .unwrap();
+        assert_eq!(page, "<html></html>");
+
+        let reply = warp::test::request()
+            .path("/assets/logo.png")
+            .header("accept-encoding", "gzip")
+            .reply(&routes)
+            .await;
+        assert_eq!(reply.body(), "png");
+        assert!(reply.headers().get(header::CONTENT_ENCODING).is_none());
+    }
+}
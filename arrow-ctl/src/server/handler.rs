@@ -1,22 +1,30 @@
+use super::super::archive::{self, ArchiveError, BowArchive};
+use super::super::controller::ControlError;
+use super::super::export::{self, ExportTarget};
 use super::super::message::*;
 use super::super::models::*;
+use super::super::schema;
 use super::database::ArrowDB;
-use super::Webserver;
+use super::{auth, rest, Webserver};
 
 use futures::{FutureExt, StreamExt};
 use uuid::Uuid;
 
 use warp::reply::json;
 use warp::ws::{WebSocket, Ws};
+use warp::Reply;
 
 use log::{debug, error, info, trace};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::sync::mpsc;
+use tokio::sync::oneshot;
 use tokio::sync::RwLock;
+use tokio::time::Instant;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
 #[derive(Debug, thiserror::Error)]
@@ -26,46 +34,373 @@ pub enum WSError {
 
     #[error("bad request: {0}.")]
     Logic(String),
+
+    #[error(transparent)]
+    Control(#[from] ControlError),
+
+    #[error("machine controller is not running.")]
+    ControllerUnavailable,
+
+    #[error("login required.")]
+    Unauthorized,
+
+    #[error("invalid user name or password.")]
+    Credentials,
+
+    #[error("{} role required.", .0.as_str())]
+    Forbidden(Role),
+
+    #[error("the client was registered by another session.")]
+    ForeignClient,
+
+    #[error(
+        "protocol version {0} is not supported, the server accepts {} to {}.",
+        MIN_PROTOCOL_VERSION,
+        PROTOCOL_VERSION
+    )]
+    Incompatible(u32),
+}
+
+impl WSError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            WSError::Sql(sqlx::Error::RowNotFound)
+            | WSError::Control(ControlError::Sql(sqlx::Error::RowNotFound)) => ErrorCode::NotFound,
+            WSError::Sql(_) | WSError::Control(ControlError::Sql(_)) => ErrorCode::Database,
+            WSError::Logic(_)
+            | WSError::Control(ControlError::NoEndCondition(_))
+            | WSError::Control(ControlError::BadInterval(_)) => ErrorCode::BadRequest,
+            WSError::Control(ControlError::Busy(_)) => ErrorCode::Busy,
+            WSError::Control(ControlError::Fault(_)) => ErrorCode::Fault,
+            WSError::Control(ControlError::NoDriver(_))
+            | WSError::Control(ControlError::Hardware(_)) => ErrorCode::Hardware,
+            WSError::ControllerUnavailable => ErrorCode::Unavailable,
+            WSError::Unauthorized | WSError::Credentials => ErrorCode::Unauthorized,
+            WSError::Forbidden(_) | WSError::ForeignClient => ErrorCode::Forbidden,
+            WSError::Incompatible(_) => ErrorCode::Incompatible,
+        }
+    }
+}
+
+/// Time a registration may wait for the websocket connection.
+pub const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time a disconnected client can resume its session with the same id.
+pub const RESUME_TIMEOUT: Duration = Duration::from_secs(300);
+/// Connected clients get pinged in this interval and are dropped if nothing
+/// is received, including pongs, for `CLIENT_TIMEOUT`.
+const PING_INTERVAL: Duration = Duration::from_secs(10);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Websocket close code sent to clients with unsupported protocol versions.
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+/// Websocket close code sent to clients whose session ended.
+const CLOSE_POLICY_VIOLATION: u16 = 1008;
+
+/// Protocol agreed on with a client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Protocol {
+    pub version: u32,
+    pub features: HashSet<Feature>,
 }
 
-#[derive(Clone, Debug)]
+impl Protocol {
+    /// Protocol of clients which start without hello, fails once a hello is
+    /// required.
+    pub fn legacy() -> std::result::Result<Self, WSError> {
+        Self::negotiate(LEGACY_PROTOCOL_VERSION, &[])
+    }
+
+    /// Accepts clients from `MIN_PROTOCOL_VERSION` on, newer clients have to
+    /// adapt to the server's version. Only features known to both and part of
+    /// the agreed version are used.
+    pub fn negotiate(version: u32, features: &[String]) -> std::result::Result<Self, WSError> {
+        if version < MIN_PROTOCOL_VERSION {
+            return Err(WSError::Incompatible(version));
+        }
+        let version = version.min(PROTOCOL_VERSION);
+        Ok(Protocol {
+            version,
+            features: features
+                .iter()
+                .filter_map(|f| f.parse::<Feature>().ok())
+                .filter(|f| f.since() <= version)
+                .collect(),
+        })
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        feature.since() <= self.version && self.features.contains(&feature)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct WSocket {
     pub sender: Option<mpsc::UnboundedSender<std::result::Result<warp::ws::Message, warp::Error>>>,
+    /// Subscribed topics, clients which never subscribed receive all updates.
+    pub topics: Option<HashSet<Topic>>,
+    /// The registration is removed at this time unless the client is
+    /// connected.
+    pub expires: Option<Instant>,
+    /// Token of the session the client was registered with, requests are
+    /// authorized with the session's current role.
+    pub session: Option<String>,
+    /// Set by the first message of a connection, clients get no optional
+    /// updates until then.
+    pub protocol: Option<Protocol>,
+    /// Protocol version the client was refused for, all further messages are
+    /// refused until it registers again.
+    pub rejected: Option<u32>,
+    /// A websocket upgrade is in progress.
+    pub connecting: bool,
+}
+
+impl WSocket {
+    pub fn is_subscribed(&self, topics: &[Topic]) -> bool {
+        match &self.topics {
+            Some(subscribed) => topics.is_empty() || topics.iter().any(|t| subscribed.contains(t)),
+            None => true,
+        }
+    }
+
+    /// Whether the client understands the update if sent unasked, clients
+    /// get no newer updates than legacy clients until their first message.
+    pub fn supports(&self, update: &WSUpdate) -> bool {
+        let protocol = match &self.protocol {
+            Some(protocol) => protocol,
+            None => return update.since() <= LEGACY_PROTOCOL_VERSION,
+        };
+        match Feature::of(update) {
+            Some(feature) => update.since() <= protocol.version && protocol.supports(feature),
+            None => update.since() <= protocol.version,
+        }
+    }
+
+    /// Protocol version errors are sent in.
+    fn version(&self) -> u32 {
+        self.protocol
+            .as_ref()
+            .map_or(LEGACY_PROTOCOL_VERSION, |p| p.version)
+    }
 }
 
 pub type Clients = Arc<RwLock<HashMap<String, WSocket>>>;
 
 type Result<T> = std::result::Result<T, warp::Rejection>;
 
-pub async fn new_client(srv: Webserver<impl ArrowDB>) -> Result<impl warp::Reply> {
+pub async fn new_client(
+    srv: Webserver<impl ArrowDB>,
+    token: Option<String>,
+) -> Result<warp::reply::Response> {
+    let role = auth::role(&srv, token.clone()).await;
+    if let Err(e) = auth::authorize(role, Role::Viewer) {
+        return Ok(rest::error_reply(e));
+    }
     let uuid = Uuid::new_v4().simple().to_string();
     let response = WSRegisterResponse {
         url: format!("/ws/{}", uuid),
     };
-    srv.sockets
-        .write()
-        .await
-        .insert(uuid, WSocket { sender: None });
+    let client = WSocket {
+        expires: Some(Instant::now() + REGISTRATION_TIMEOUT),
+        session: token,
+        ..Default::default()
+    };
+    srv.sockets.write().await.insert(uuid, client);
     trace!("sockets: {:#?}", srv.sockets.read().await);
-    Ok(json(&response))
+    Ok(json(&response).into_response())
 }
 
-pub async fn delete_client<F: ArrowDB>(srv: Webserver<F>, id: String) -> Result<impl warp::Reply> {
+pub async fn delete_client<F: ArrowDB>(
+    srv: Webserver<F>,
+    id: String,
+    role: Option<Role>,
+) -> Result<warp::reply::Response> {
+    if let Err(e) = auth::authorize(role, Role::Viewer) {
+        return Ok(rest::error_reply(e));
+    }
     trace!("deleting client: {}", id);
     srv.sockets.write().await.remove(&id);
-    Ok(warp::http::StatusCode::OK)
+    Ok(warp::http::StatusCode::OK.into_response())
+}
+
+/// Removes the clients registered with one of the ended sessions and closes
+/// their connections.
+pub async fn close_sessions<F: ArrowDB>(srv: &Webserver<F>, sessions: &[String]) {
+    if sessions.is_empty() {
+        return;
+    }
+    srv.sockets.write().await.retain(|id, client| {
+        let ended = matches!(&client.session, Some(s) if sessions.contains(s));
+        if ended {
+            info!(target: "arrow::web::ws", "{} closed, its session ended", id);
+            if let Some(sender) = &client.sender {
+                let _ = sender.send(Ok(warp::ws::Message::close_with(
+                    CLOSE_POLICY_VIOLATION,
+                    "session ended",
+                )));
+            }
+        }
+        !ended
+    });
+}
+
+/// Sends the data as CSV file download.
+pub async fn export_csv<F: ArrowDB>(
+    srv: Webserver<F>,
+    target: ExportTarget,
+    role: Option<Role>,
+) -> Result<warp::reply::Response> {
+    if let Err(e) = auth::authorize(role, Role::Viewer) {
+        return Ok(rest::error_reply(e));
+    }
+    match export::export(&srv.db, target).await {
+        Ok(csv) => {
+            let reply = warp::reply::with_header(csv, "content-type", "text/csv; charset=utf-8");
+            let disposition = format!("attachment; filename=\"{}\"", target.file_name());
+            Ok(warp::reply::with_header(reply, "content-disposition", disposition).into_response())
+        }
+        // a rejection would be reported as 405 by the other routes
+        Err(e @ sqlx::Error::RowNotFound) => Ok(rest::error_reply(e.into())),
+        Err(e) => {
+            error!(target: "arrow::web", "cannot export {:?}: '{}'", target, e);
+            Ok(warp::reply::with_status(
+                format!("cannot export data: {}", e),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response())
+        }
+    }
+}
+
+/// Sends the bow with all its data as JSON archive download.
+pub async fn export_archive<F: ArrowDB>(
+    srv: Webserver<F>,
+    id: i32,
+    role: Option<Role>,
+) -> Result<warp::reply::Response> {
+    if let Err(e) = auth::authorize(role, Role::Viewer) {
+        return Ok(rest::error_reply(e));
+    }
+    match archive::export_bow(&srv.db, id).await {
+        Ok(archive) => {
+            let disposition = format!("attachment; filename=\"bow-{}.json\"", id);
+            Ok(
+                warp::reply::with_header(json(&archive), "content-disposition", disposition)
+                    .into_response(),
+            )
+        }
+        Err(e) => archive_error(ArchiveError::Sql(e)),
+    }
+}
+
+/// Stores the bow of the archive, replies with the new bow.
+pub async fn import_archive<F: ArrowDB>(
+    srv: Webserver<F>,
+    role: Option<Role>,
+    archive: BowArchive,
+) -> Result<warp::reply::Response> {
+    if let Err(e) = auth::authorize(role, Role::Operator) {
+        return Ok(rest::error_reply(e));
+    }
+    match archive::import_bow(&srv.db, archive).await {
+        Ok(bow) => Ok(json(&bow).into_response()),
+        Err(e) => archive_error(e),
+    }
+}
+
+/// Sends the JSON Schema of the websocket protocol, or its TypeScript
+/// definitions.
+pub async fn protocol_schema(typescript: bool) -> Result<warp::reply::Response> {
+    let protocol = schema::protocol();
+    if typescript {
+        let ts = schema::typescript(&protocol);
+        Ok(
+            warp::reply::with_header(ts, "content-type", "application/typescript; charset=utf-8")
+                .into_response(),
+        )
+    } else {
+        Ok(json(&protocol).into_response())
+    }
+}
+
+fn archive_error(e: ArchiveError) -> Result<warp::reply::Response> {
+    let status = match e {
+        ArchiveError::Sql(sqlx::Error::RowNotFound) => return Err(warp::reject::not_found()),
+        ArchiveError::Version(_) | ArchiveError::Invalid(_) => warp::http::StatusCode::BAD_REQUEST,
+        ArchiveError::Sql(_) => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    error!(target: "arrow::web", "archive error: '{}'", e);
+    Ok(warp::reply::with_status(e.to_string(), status).into_response())
 }
 
 pub async fn ws_connect<F: ArrowDB + 'static>(
     srv: Webserver<F>,
     ws: Ws,
     id: String,
-) -> Result<impl warp::Reply> {
+    token: Option<String>,
+) -> Result<warp::reply::Response> {
     trace!("trying to connect: {}", id);
-    let client = srv.sockets.read().await.get(&id).cloned();
+    let role = auth::role(&srv, token.clone()).await;
+    if let Err(e) = auth::authorize(role, Role::Viewer) {
+        return Ok(rest::error_reply(e));
+    }
+    // the registration is claimed under the write lock, so only one of
+    // several simultaneous upgrades succeeds
+    let client = match srv.sockets.write().await.get_mut(&id) {
+        // requests are authorized with the registration's session
+        Some(c) if srv.authentication && c.session != token => {
+            return Ok(rest::error_reply(WSError::ForeignClient));
+        }
+        Some(c) if c.sender.is_some() || c.connecting => None,
+        Some(c) => {
+            c.connecting = true;
+            Some(c.clone())
+        }
+        None => return Err(warp::reject::not_found()),
+    };
     match client {
-        Some(c) => Ok(ws.on_upgrade(move |socket| client_connection(socket, id, srv, c))),
-        None => Err(warp::reject::not_found()),
+        Some(c) => {
+            let claim = UpgradeClaim {
+                srv: Some(srv.clone()),
+                id: id.clone(),
+            };
+            Ok(ws
+                .on_upgrade(move |socket| {
+                    claim.upgraded();
+                    client_connection(socket, id, srv, c)
+                })
+                .into_response())
+        }
+        None => Ok(warp::reply::with_status(
+            "client is already connected",
+            warp::http::StatusCode::CONFLICT,
+        )
+        .into_response()),
+    }
+}
+
+/// Releases the registration claimed by a websocket upgrade which failed.
+struct UpgradeClaim<F: ArrowDB + 'static> {
+    srv: Option<Webserver<F>>,
+    id: String,
+}
+
+impl<F: ArrowDB + 'static> UpgradeClaim<F> {
+    /// The connection takes over the registration.
+    fn upgraded(mut self) {
+        self.srv = None;
+    }
+}
+
+impl<F: ArrowDB + 'static> Drop for UpgradeClaim<F> {
+    fn drop(&mut self) {
+        let (srv, id) = match (self.srv.take(), tokio::runtime::Handle::try_current()) {
+            (Some(srv), Ok(_)) => (srv, std::mem::take(&mut self.id)),
+            _ => return,
+        };
+        tokio::spawn(async move {
+            if let Some(client) = srv.sockets.write().await.get_mut(&id) {
+                client.connecting = false;
+            }
+        });
     }
 }
 
@@ -86,27 +421,56 @@ async fn client_connection<F: ArrowDB>(
     }));
 
     client.sender = Some(client_sender.clone());
+    client.connecting = false;
+    client.expires = None;
+    // the protocol is negotiated again on every connection, resumed
+    // subscriptions are sent with the hello reply
+    client.protocol = None;
     clients.sockets.write().await.insert(id.clone(), client);
 
     info!(target: "arrow::web::ws", "{} connected", id);
 
-    while let Some(result) = client_ws_rcv.next().await {
-        let msg = match result {
-            Ok(msg) => msg,
-            Err(e) => {
-                error!(target: "arrow::web::ws", "error receiving ws message for id: {}): {}", id.clone(), e);
-                break;
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    let mut last_seen = Instant::now();
+    loop {
+        tokio::select! {
+            result = client_ws_rcv.next() => {
+                let msg = match result {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(e)) => {
+                        error!(target: "arrow::web::ws", "error receiving ws message for id: {}): {}", id.clone(), e);
+                        break;
+                    }
+                    None => break,
+                };
+                last_seen = Instant::now();
+                if msg.is_close() {
+                    break;
+                }
+                if msg.is_text() || msg.is_binary() {
+                    handle_ws_message(&id, msg, &clients, client_sender.clone()).await;
+                }
             }
-        };
-        handle_ws_message(&id, msg, &clients, client_sender.clone()).await;
+            _ = ping.tick() => {
+                if last_seen.elapsed() > CLIENT_TIMEOUT {
+                    info!(target: "arrow::web::ws", "{} timed out", id);
+                    break;
+                }
+                let _ = client_sender.send(Ok(warp::ws::Message::ping(Vec::new())));
+            }
+        }
     }
 
-    clients.sockets.write().await.remove(&id);
+    // keep the subscriptions for a while, so the client can resume
+    if let Some(client) = clients.sockets.write().await.get_mut(&id) {
+        client.sender = None;
+        client.expires = Some(Instant::now() + RESUME_TIMEOUT);
+    }
     info!(target: "arrow::web::ws", "{} disconnected", id);
 }
 
 pub async fn handle_ws_message<F: ArrowDB>(
-    _id: &String,
+    id: &str,
     msg: warp::ws::Message,
     srv: &Webserver<F>,
     response_channel: mpsc::UnboundedSender<std::result::Result<warp::ws::Message, warp::Error>>,
@@ -115,39 +479,63 @@ pub async fn handle_ws_message<F: ArrowDB>(
     let message = match WSMessage::try_from(msg) {
         Ok(m) => m,
         Err(e) => {
-            let err = format!("error parsing message '{}'", e);
             error!(target: "arrow::web::ws", "error parsing message '{}'", e);
-            let _ = response_channel
-                .send(Ok(WSMessage::Response(WSUpdate::Error(err)).into()))
-                .map_err(|e| {
-                    error!(target: "arrow::server::ws", "Cannot send response to client: {}", e);
-                });
+            let err = WSMessage::error(
+                client_version(srv, id).await,
+                None,
+                ErrorCode::BadMessage,
+                format!("error parsing message '{}'", e),
+            );
+            let _ = response_channel.send(Ok(err.into())).map_err(|e| {
+                error!(target: "arrow::server::ws", "Cannot send response to client: {}", e);
+            });
             return;
         }
     };
-    if let WSMessage::Request(request) = message {
-        let response = match request {
-            WSRequest::ListBows {} => list_bows(srv).await,
-            WSRequest::AddBow(bow) => add_bow(srv, bow).await,
-            WSRequest::AddArrow(arrow) => add_arrow(srv, arrow).await,
-            WSRequest::StartMeasure(measure) => start_measure(srv, measure).await,
-            WSRequest::NewMeasureSeries(series) => add_measure_series(srv, series).await,
-            WSRequest::ListMeasureSeries { bow_id } => list_measure_series(srv, bow_id).await,
-            WSRequest::ListArrows { bow_id } => list_arrows(srv, bow_id).await,
-            WSRequest::ListMeasures { series_id } => list_measures(srv, series_id).await,
-            WSRequest::ListMeasurePoints { measure_id } => {
-                list_measure_points(srv, measure_id).await
-            }
-            WSRequest::Command(command) => handle_arrow_command(srv, command).await,
-        };
+    let accepted = match &message {
+        WSMessage::Hello { version, features } => hello(srv, id, *version, features).await,
+        _ => accept_legacy(srv, id).await,
+    };
+    let handled = accepted.is_err() || matches!(message, WSMessage::Hello { .. });
+    let replies = match accepted {
+        Ok(replies) => replies.into_iter().map(Into::into).collect(),
+        Err(e) => {
+            // clients sending a hello know error messages
+            let version = match &message {
+                WSMessage::Hello { .. } => PROTOCOL_VERSION,
+                _ => LEGACY_PROTOCOL_VERSION,
+            };
+            refuse(srv, id, version, e).await
+        }
+    };
+    for reply in replies {
+        let _ = response_channel.send(Ok(reply)).map_err(|e| {
+            error!(target: "arrow::server::ws", "Cannot send response to client: {}", e);
+        });
+    }
+    if handled {
+        return;
+    }
+    if let WSMessage::Request {
+        id: request_id,
+        request,
+    } = message
+    {
+        let role = client_role(srv, id).await;
+        let response = execute(srv, Some(id), role, request).await;
 
-        let msg: warp::ws::Message = WSMessage::Response(match response {
-            Ok(r) => r,
-            Err(e) => {
-                let err = format!("Error while executing Request: {}", e);
-                WSUpdate::Error(err)
-            }
-        })
+        let msg: warp::ws::Message = match response {
+            Ok(update) => WSMessage::Response {
+                id: request_id,
+                update,
+            },
+            Err(e) => WSMessage::error(
+                client_version(srv, id).await,
+                request_id,
+                e.code(),
+                format!("Error while executing Request: {}", e),
+            ),
+        }
         .into();
         let _ = response_channel.send(Ok(msg)).map_err(|e| {
             error!(target: "arrow::server::ws", "Cannot send response to client: {}", e);
@@ -155,11 +543,166 @@ pub async fn handle_ws_message<F: ArrowDB>(
     }
 }
 
+/// Stores the protocol of the client's hello and answers with the server's
+/// version and the features both sides support, followed by the resumed
+/// subscriptions if the client understands them.
+async fn hello<F: ArrowDB>(
+    srv: &Webserver<F>,
+    id: &str,
+    version: u32,
+    features: &[String],
+) -> std::result::Result<Vec<WSMessage>, WSError> {
+    let mut sockets = srv.sockets.write().await;
+    let client = sockets.get_mut(id);
+    if let Some(version) = client.as_ref().and_then(|c| c.rejected) {
+        return Err(WSError::Incompatible(version));
+    }
+    if matches!(&client, Some(c) if c.protocol.is_some()) {
+        return Err(WSError::Logic("hello must be the first message".into()));
+    }
+    let protocol = Protocol::negotiate(version, features)?;
+    info!(target: "arrow::web::ws", "{} speaks protocol version {}", id, protocol.version);
+    let mut replies = vec![WSMessage::Hello {
+        version: PROTOCOL_VERSION,
+        features: Feature::ALL
+            .iter()
+            .filter(|f| protocol.supports(**f))
+            .map(|f| f.as_str().to_string())
+            .collect(),
+    }];
+    if let Some(client) = client {
+        if let Some(topics) = client
+            .topics
+            .as_ref()
+            .filter(|_| protocol.supports(Feature::Subscriptions))
+        {
+            let topics = topics.iter().copied().collect();
+            replies.push(WSMessage::Update(WSUpdate::Subscriptions(topics)));
+        }
+        client.protocol = Some(protocol);
+    }
+    Ok(replies)
+}
+
+/// Serves clients which start without hello with the legacy protocol,
+/// unless a hello is required or the client was refused before.
+async fn accept_legacy<F: ArrowDB>(
+    srv: &Webserver<F>,
+    id: &str,
+) -> std::result::Result<Vec<WSMessage>, WSError> {
+    if let Some(client) = srv.sockets.write().await.get_mut(id) {
+        if let Some(version) = client.rejected {
+            return Err(WSError::Incompatible(version));
+        }
+        if client.protocol.is_none() {
+            client.protocol = Some(Protocol::legacy()?);
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// Answers a refused hello or legacy start of a client speaking `version`,
+/// clients with an incompatible version are disconnected and refused until
+/// they register again.
+async fn refuse<F: ArrowDB>(
+    srv: &Webserver<F>,
+    id: &str,
+    version: u32,
+    e: WSError,
+) -> Vec<warp::ws::Message> {
+    error!(target: "arrow::web::ws", "refusing protocol of {}: {}", id, e);
+    let err = WSMessage::error(version, None, e.code(), e.to_string());
+    match e {
+        WSError::Incompatible(version) => {
+            if let Some(client) = srv.sockets.write().await.get_mut(id) {
+                client.rejected = Some(version);
+            }
+            vec![
+                err.into(),
+                warp::ws::Message::close_with(
+                    CLOSE_PROTOCOL_ERROR,
+                    "incompatible protocol version",
+                ),
+            ]
+        }
+        _ => vec![err.into()],
+    }
+}
+
+/// Protocol version of the client, legacy until negotiated.
+async fn client_version<F: ArrowDB>(srv: &Webserver<F>, id: &str) -> u32 {
+    srv.sockets
+        .read()
+        .await
+        .get(id)
+        .map_or(LEGACY_PROTOCOL_VERSION, WSocket::version)
+}
+
+/// Current role of the session the websocket client was registered with,
+/// `None` once the session ended.
+async fn client_role<F: ArrowDB>(srv: &Webserver<F>, id: &str) -> Option<Role> {
+    if !srv.authentication {
+        return Some(Role::Operator);
+    }
+    let session = srv
+        .sockets
+        .read()
+        .await
+        .get(id)
+        .and_then(|c| c.session.clone());
+    auth::role(srv, Some(session?)).await
+}
+
+/// Executes a request if `role` permits it, `client` is the websocket
+/// connection it was received on, if any.
+pub async fn execute<F: ArrowDB>(
+    srv: &Webserver<F>,
+    client: Option<&str>,
+    role: Option<Role>,
+    request: WSRequest,
+) -> std::result::Result<WSUpdate, WSError> {
+    auth::authorize(role, request.role())?;
+    match request {
+        WSRequest::ListBows {} => list_bows(srv).await,
+        WSRequest::GetBow { id } => get_bow(srv, id).await,
+        WSRequest::AddBow(bow) => add_bow(srv, bow).await,
+        WSRequest::UpdateBow(bow) => update_bow(srv, bow).await,
+        WSRequest::DeleteBow { id } => delete_bow(srv, id).await,
+        WSRequest::AddArrow(arrow) => add_arrow(srv, arrow).await,
+        WSRequest::UpdateArrow(arrow) => update_arrow(srv, arrow).await,
+        WSRequest::DeleteArrow { id } => delete_arrow(srv, id).await,
+        WSRequest::StartMeasure(measure) => start_measure(srv, measure).await,
+        WSRequest::NewMeasureSeries(series) => add_measure_series(srv, series).await,
+        WSRequest::RenameMeasureSeries { id, name } => rename_measure_series(srv, id, name).await,
+        WSRequest::DeleteMeasureSeries { id } => delete_measure_series(srv, id).await,
+        WSRequest::DeleteMeasure { id } => delete_measure(srv, id).await,
+        WSRequest::ListMeasureSeries { bow_id } => list_measure_series(srv, bow_id).await,
+        WSRequest::ListArrows { bow_id } => list_arrows(srv, bow_id).await,
+        WSRequest::ListMeasures { series_id } => list_measures(srv, series_id).await,
+        WSRequest::ListMeasurePoints { measure_id } => list_measure_points(srv, measure_id).await,
+        WSRequest::ListMeasureResults { series_id } => list_measure_results(srv, series_id).await,
+        WSRequest::Command(command) => handle_arrow_command(srv, command).await,
+        WSRequest::GetHealth {} => Ok(WSUpdate::Health {
+            database: *srv.database_state.read().await,
+        }),
+        WSRequest::Subscribe(topics) => subscribe(srv, client, topics, true).await,
+        WSRequest::Unsubscribe(topics) => subscribe(srv, client, topics, false).await,
+    }
+}
+
 async fn list_bows<F: ArrowDB>(srv: &Webserver<F>) -> std::result::Result<WSUpdate, WSError> {
     let bows = srv.db.list_bows().await?;
     Ok(WSUpdate::BowList(bows))
 }
 
+async fn get_bow<F: ArrowDB>(
+    srv: &Webserver<F>,
+    id: i32,
+) -> std::result::Result<WSUpdate, WSError> {
+    let bow = srv.db.get_bow(id).await?;
+    Ok(WSUpdate::Bow(bow))
+}
+
 async fn add_bow<F: ArrowDB>(
     srv: &Webserver<F>,
     bow: Bow,
@@ -168,6 +711,22 @@ async fn add_bow<F: ArrowDB>(
     Ok(WSUpdate::BowList(vec![bow]))
 }
 
+async fn update_bow<F: ArrowDB>(
+    srv: &Webserver<F>,
+    bow: Bow,
+) -> std::result::Result<WSUpdate, WSError> {
+    let bow = srv.db.update_bow(bow).await?;
+    Ok(WSUpdate::BowList(vec![bow]))
+}
+
+async fn delete_bow<F: ArrowDB>(
+    srv: &Webserver<F>,
+    id: i32,
+) -> std::result::Result<WSUpdate, WSError> {
+    srv.db.delete_bow(id).await?;
+    Ok(removed("bow", id))
+}
+
 async fn add_measure_series<F: ArrowDB>(
     srv: &Webserver<F>,
     series: MeasureSeries,
@@ -181,6 +740,23 @@ async fn add_measure_series<F: ArrowDB>(
     Ok(WSUpdate::MeasureSeriesList(vec![series]))
 }
 
+async fn rename_measure_series<F: ArrowDB>(
+    srv: &Webserver<F>,
+    id: i32,
+    name: String,
+) -> std::result::Result<WSUpdate, WSError> {
+    let series = srv.db.rename_measure_series(id, name).await?;
+    Ok(WSUpdate::MeasureSeriesList(vec![series]))
+}
+
+async fn delete_measure_series<F: ArrowDB>(
+    srv: &Webserver<F>,
+    id: i32,
+) -> std::result::Result<WSUpdate, WSError> {
+    srv.db.delete_measure_series(id).await?;
+    Ok(removed("measure_series", id))
+}
+
 async fn list_measure_series<F: ArrowDB>(
     srv: &Webserver<F>,
     bow_id: i32,
@@ -205,6 +781,22 @@ async fn add_arrow<F: ArrowDB>(
     Ok(WSUpdate::ArrowList(vec![arrow]))
 }
 
+async fn update_arrow<F: ArrowDB>(
+    srv: &Webserver<F>,
+    arrow: Arrow,
+) -> std::result::Result<WSUpdate, WSError> {
+    let arrow = srv.db.update_arrow(arrow).await?;
+    Ok(WSUpdate::ArrowList(vec![arrow]))
+}
+
+async fn delete_arrow<F: ArrowDB>(
+    srv: &Webserver<F>,
+    id: i32,
+) -> std::result::Result<WSUpdate, WSError> {
+    srv.db.delete_arrow(id).await?;
+    Ok(removed("arrow", id))
+}
+
 async fn list_measures<F: ArrowDB>(
     srv: &Webserver<F>,
     series_id: i32,
@@ -213,11 +805,26 @@ async fn list_measures<F: ArrowDB>(
     Ok(WSUpdate::MeasureList(measures))
 }
 
+async fn delete_measure<F: ArrowDB>(
+    srv: &Webserver<F>,
+    id: i32,
+) -> std::result::Result<WSUpdate, WSError> {
+    srv.db.delete_measure(id).await?;
+    Ok(removed("measure", id))
+}
+
+fn removed(table: &str, id: i32) -> WSUpdate {
+    WSUpdate::Removed {
+        table: table.into(),
+        ids: vec![id],
+    }
+}
+
 async fn start_measure<F: ArrowDB>(
     srv: &Webserver<F>,
     measure: Measure,
 ) -> std::result::Result<WSUpdate, WSError> {
-    let measure = srv.db.add_measure(measure).await?;
+    let measure = send_control(srv, |reply| ControlMessage::StartMeasure(measure, reply)).await?;
     Ok(WSUpdate::MeasureList(vec![measure]))
 }
 
@@ -229,23 +836,78 @@ async fn list_measure_points<F: ArrowDB>(
     Ok(WSUpdate::MeasurePointList(measure_points))
 }
 
+async fn list_measure_results<F: ArrowDB>(
+    srv: &Webserver<F>,
+    series_id: i32,
+) -> std::result::Result<WSUpdate, WSError> {
+    let results = srv.db.list_measure_results(series_id).await?;
+    Ok(WSUpdate::MeasureResultList(results))
+}
+
 async fn handle_arrow_command<F: ArrowDB>(
     srv: &Webserver<F>,
     command: MachineCommand,
 ) -> std::result::Result<WSUpdate, WSError> {
-    Ok(WSUpdate::Alive {})
+    let status = send_control(srv, |reply| ControlMessage::Command(command, reply)).await?;
+    Ok(WSUpdate::Status(status))
+}
+
+async fn subscribe<F: ArrowDB>(
+    srv: &Webserver<F>,
+    client: Option<&str>,
+    topics: Vec<Topic>,
+    subscribe: bool,
+) -> std::result::Result<WSUpdate, WSError> {
+    let id =
+        client.ok_or_else(|| WSError::Logic("subscriptions need a websocket connection".into()))?;
+    let mut sockets = srv.sockets.write().await;
+    let client = sockets
+        .get_mut(id)
+        .ok_or_else(|| WSError::Logic(format!("unknown client '{}'", id)))?;
+    let subscribed = client.topics.get_or_insert_with(HashSet::new);
+    for topic in topics {
+        if subscribe {
+            subscribed.insert(topic);
+        } else {
+            subscribed.remove(&topic);
+        }
+    }
+    Ok(WSUpdate::Subscriptions(
+        subscribed.iter().copied().collect(),
+    ))
+}
+
+/// Sends a message to the controller and waits for its reply.
+async fn send_control<F: ArrowDB, T>(
+    srv: &Webserver<F>,
+    message: impl FnOnce(ControlReply<T>) -> ControlMessage,
+) -> std::result::Result<T, WSError> {
+    let (tx, rx) = oneshot::channel();
+    srv.control_tx
+        .send(message(tx))
+        .await
+        .map_err(|_| WSError::ControllerUnavailable)?;
+    Ok(rx.await.map_err(|_| WSError::ControllerUnavailable)??)
 }
 
 #[cfg(test)]
 mod test {
     use super::super::database::traits::MockDB;
     use super::*;
+    use warp::Filter;
+
     fn mock_srv(db: MockDB) -> Webserver<impl ArrowDB> {
         let (tx, _rx) = tokio::sync::mpsc::channel(8);
+        let (control_tx, _control_rx) = control_channel(8);
         Webserver {
             shutdown_tx: tx,
+            control_tx,
             sockets: Arc::new(RwLock::new(HashMap::new())),
             db,
+            database_state: Arc::new(RwLock::new(ConnectionState::Connected)),
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            authentication: false,
+            frontend: None,
         }
     }
     #[tokio::test]
@@ -263,8 +925,12 @@ mod test {
         let srv = mock_srv(db);
 
         handle_ws_message(
-            &"".into(),
-            WSMessage::Request(WSRequest::ListBows {}).into(),
+            "",
+            WSMessage::Request {
+                id: Some("1".into()),
+                request: WSRequest::ListBows {},
+            }
+            .into(),
             &srv,
             tx,
         )
@@ -272,7 +938,589 @@ mod test {
         let response = rx.recv().await.unwrap();
         assert_eq!(
             response.unwrap(),
-            WSMessage::Response(WSUpdate::BowList(vec![bow])).into()
+            WSMessage::Response {
+                id: Some("1".into()),
+                update: WSUpdate::BowList(vec![bow])
+            }
+            .into()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_bow() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut db = MockDB::new();
+        db.expect_delete_bow()
+            .withf(|id| *id == 4)
+            .return_once(|_| Ok(()));
+        let srv = mock_srv(db);
+
+        let request = r#"{"request": {"deletebow": {"id": 4}}}"#;
+        handle_ws_message("", warp::ws::Message::text(request), &srv, tx).await;
+        let response = WSMessage::try_from(rx.recv().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(
+            response,
+            WSMessage::Response {
+                update: WSUpdate::Removed { table, ids },
+                ..
+            } if table == "bow" && ids == vec![4]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_error_codes() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut db = MockDB::new();
+        db.expect_list_arrows()
+            .return_once(|_| Err(sqlx::Error::RowNotFound));
+        let srv = mock_srv(db);
+        let client = WSocket {
+            protocol: Protocol::negotiate(PROTOCOL_VERSION, &[]).ok(),
+            ..Default::default()
+        };
+        srv.sockets.write().await.insert("tablet".into(), client);
+
+        let request = r#"{"request": {"id": "a", "listarrows": {"bow_id": 3}}}"#;
+        handle_ws_message("tablet", warp::ws::Message::text(request), &srv, tx.clone()).await;
+        let response = WSMessage::try_from(rx.recv().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(
+            response,
+            WSMessage::Error { id: Some(id), code: ErrorCode::NotFound, .. } if id == "a"
+        ));
+
+        handle_ws_message("tablet", warp::ws::Message::text("{}"), &srv, tx).await;
+        let response = WSMessage::try_from(rx.recv().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(
+            response,
+            WSMessage::Error {
+                id: None,
+                code: ErrorCode::BadMessage,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_to_subscribers() {
+        let srv = mock_srv(MockDB::new());
+        let mut receivers = Vec::new();
+        for id in ["unsubscribed", "subscriber"] {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            let client = WSocket {
+                sender: Some(tx),
+                topics: None,
+                expires: None,
+                session: None,
+                protocol: Protocol::negotiate(PROTOCOL_VERSION, &[]).ok(),
+                rejected: None,
+                connecting: false,
+            };
+            srv.sockets.write().await.insert(id.into(), client);
+            receivers.push(rx);
+        }
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        handle_ws_message(
+            "subscriber",
+            WSMessage::Request {
+                id: None,
+                request: WSRequest::Subscribe(vec![Topic::Measure(1)]),
+            }
+            .into(),
+            &srv,
+            tx,
+        )
+        .await;
+
+        let live = |measure_id| WSUpdate::LiveMeasurePoints {
+            measure_id,
+            points: Vec::new(),
+        };
+        srv.broadcast(live(2)).await;
+        srv.broadcast(live(1)).await;
+        srv.broadcast(WSUpdate::Error("error".into())).await;
+
+        let mut subscriber = receivers.pop().unwrap();
+        let mut unsubscribed = receivers.pop().unwrap();
+        for expected in [live(2), live(1), WSUpdate::Error("error".into())] {
+            assert_eq!(
+                unsubscribed.recv().await.unwrap().unwrap(),
+                warp::ws::Message::text(serde_json::to_string(&expected).unwrap())
+            );
+        }
+        for expected in [live(1), WSUpdate::Error("error".into())] {
+            assert_eq!(
+                subscriber.recv().await.unwrap().unwrap(),
+                warp::ws::Message::text(serde_json::to_string(&expected).unwrap())
+            );
+        }
+        assert!(subscriber.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_health_follows_listener() {
+        fn listener() -> MockDB {
+            let mut listener = MockDB::new();
+            listener.expect_listener().returning(|tx| {
+                let _ = tx.send(WSUpdate::Health {
+                    database: ConnectionState::Disconnected,
+                });
+                Ok(())
+            });
+            listener
+        }
+        let mut db = MockDB::new();
+        db.expect_clone().returning(|| {
+            let mut broadcaster = MockDB::new();
+            broadcaster.expect_clone().returning(listener);
+            broadcaster
+        });
+        let srv = mock_srv(db);
+        srv.clone().listen();
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let request = r#"{"request": {"gethealth": {}}}"#;
+        handle_ws_message("", warp::ws::Message::text(request), &srv, tx).await;
+        let response = WSMessage::try_from(rx.recv().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(
+            response,
+            WSMessage::Response {
+                update: WSUpdate::Health {
+                    database: ConnectionState::Disconnected
+                },
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stale_registrations_expire() {
+        let srv = mock_srv(MockDB::new());
+        let now = Instant::now();
+        for (id, expires) in [
+            ("connected", None),
+            ("waiting", Some(now + REGISTRATION_TIMEOUT)),
+            ("stale", Some(now)),
+        ] {
+            let client = WSocket {
+                expires,
+                ..Default::default()
+            };
+            srv.sockets.write().await.insert(id.into(), client);
+        }
+        srv.expire_clients().await;
+
+        let sockets = srv.sockets.read().await;
+        assert!(sockets.contains_key("connected"));
+        assert!(sockets.contains_key("waiting"));
+        assert!(!sockets.contains_key("stale"));
+    }
+
+    #[tokio::test]
+    async fn test_ended_sessions_close_clients() {
+        // logout works on a clone of the server
+        fn mock_db() -> MockDB {
+            let mut db = MockDB::new();
+            db.expect_list_bows().returning(|| Ok(Vec::new()));
+            db.expect_clone().returning(mock_db);
+            db
+        }
+        let mut srv = mock_srv(mock_db());
+        srv.authentication = true;
+        let now = Instant::now();
+        let mut receivers = Vec::new();
+        for (token, expires) in [("current", now + auth::SESSION_TIMEOUT), ("old", now)] {
+            let session = auth::Session {
+                user: "student".into(),
+                role: Role::Viewer,
+                expires,
+            };
+            srv.sessions.write().await.insert(token.into(), session);
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            let client = WSocket {
+                sender: Some(tx),
+                session: Some(token.into()),
+                protocol: Protocol::negotiate(PROTOCOL_VERSION, &[]).ok(),
+                ..Default::default()
+            };
+            srv.sockets.write().await.insert(token.into(), client);
+            receivers.push(rx);
+        }
+        let list_bows = |id: &'static str| {
+            let srv = srv.clone();
+            async move {
+                let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+                let request = WSMessage::Request {
+                    id: None,
+                    request: WSRequest::ListBows {},
+                };
+                handle_ws_message(id, request.into(), &srv, tx).await;
+                WSMessage::try_from(rx.recv().await.unwrap().unwrap()).unwrap()
+            }
+        };
+        let unauthorized = |msg: &WSMessage| {
+            matches!(
+                msg,
+                WSMessage::Error {
+                    code: ErrorCode::Unauthorized,
+                    ..
+                }
+            )
+        };
+
+        assert!(matches!(
+            list_bows("current").await,
+            WSMessage::Response { .. }
+        ));
+        assert!(unauthorized(&list_bows("old").await));
+        srv.expire_clients().await;
+        assert!(!srv.sockets.read().await.contains_key("old"));
+        assert!(receivers[1].recv().await.unwrap().unwrap().is_close());
+
+        auth::logout(srv.clone(), Some("current".into()))
+            .await
+            .unwrap();
+        assert!(srv.sockets.read().await.is_empty());
+        assert!(receivers[0].recv().await.unwrap().unwrap().is_close());
+        // the client is gone, so the error is sent in the legacy shape
+        assert!(matches!(
+            list_bows("current").await,
+            WSMessage::Response {
+                update: WSUpdate::Error(message),
+                ..
+            } if message.contains("login required")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resume_restores_subscriptions() {
+        // every connection works on a clone of the server
+        fn mock_db() -> MockDB {
+            let mut db = MockDB::new();
+            db.expect_clone().returning(mock_db);
+            db
+        }
+        let srv = mock_srv(mock_db());
+        let client = WSocket {
+            topics: Some(vec![Topic::Status].into_iter().collect()),
+            expires: Some(Instant::now() + RESUME_TIMEOUT),
+            ..Default::default()
+        };
+        srv.sockets.write().await.insert("tablet".into(), client);
+        let connect = {
+            let srv = srv.clone();
+            warp::ws()
+                .and(warp::path!("ws" / String))
+                .and_then(move |ws, id| ws_connect(srv.clone(), ws, id, None))
+        };
+
+        let mut ws = warp::test::ws()
+            .path("/ws/tablet")
+            .handshake(connect.clone())
+            .await
+            .unwrap();
+        ws.send_text(r#"{"hello": {"version": 2, "features": ["subscriptions"]}}"#)
+            .await;
+        let mut messages = Vec::new();
+        while messages.len() < 2 {
+            let msg = ws.recv().await.unwrap();
+            if !msg.is_ping() {
+                messages.push(WSMessage::try_from(msg).unwrap());
+            }
+        }
+        let update = messages.pop().unwrap();
+        assert!(matches!(messages[0], WSMessage::Hello { .. }));
+        assert!(matches!(
+            update,
+            WSMessage::Update(WSUpdate::Subscriptions(topics)) if topics == vec![Topic::Status]
+        ));
+        assert!(srv.sockets.read().await["tablet"].expires.is_none());
+
+        // the session is in use, a second connection is refused
+        assert!(warp::test::ws()
+            .path("/ws/tablet")
+            .handshake(connect)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_in_progress_is_refused() {
+        fn mock_db() -> MockDB {
+            let mut db = MockDB::new();
+            db.expect_clone().returning(mock_db);
+            db
+        }
+        let srv = mock_srv(mock_db());
+        srv.sockets
+            .write()
+            .await
+            .insert("tablet".into(), WSocket::default());
+        let connect = {
+            let srv = srv.clone();
+            warp::ws()
+                .and(warp::path!("ws" / String))
+                .and_then(move |ws, id| ws_connect(srv.clone(), ws, id, None))
+        };
+
+        // the upgrade claims the registration, the claim is released as the
+        // test request cannot be upgraded
+        let reply = warp::test::request()
+            .path("/ws/tablet")
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .reply(&connect)
+            .await;
+        assert_eq!(reply.status(), warp::http::StatusCode::SWITCHING_PROTOCOLS);
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(!srv.sockets.read().await["tablet"].connecting);
+
+        srv.sockets
+            .write()
+            .await
+            .get_mut("tablet")
+            .unwrap()
+            .connecting = true;
+        assert!(warp::test::ws()
+            .path("/ws/tablet")
+            .handshake(connect)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_clients_connect_with_their_session() {
+        fn mock_db() -> MockDB {
+            let mut db = MockDB::new();
+            db.expect_clone().returning(mock_db);
+            db
+        }
+        let mut srv = mock_srv(mock_db());
+        srv.authentication = true;
+        for (token, role) in [("viewer", Role::Viewer), ("operator", Role::Operator)] {
+            let session = auth::Session {
+                user: token.into(),
+                role,
+                expires: Instant::now() + auth::SESSION_TIMEOUT,
+            };
+            srv.sessions.write().await.insert(token.into(), session);
+        }
+        let client = WSocket {
+            session: Some("operator".into()),
+            ..Default::default()
+        };
+        srv.sockets.write().await.insert("tablet".into(), client);
+        let connect = {
+            let srv = srv.clone();
+            warp::ws()
+                .and(warp::path!("ws" / String))
+                .and(auth::token())
+                .and_then(move |ws, id, token| ws_connect(srv.clone(), ws, id, token))
+        };
+
+        let reply = warp::test::request()
+            .path("/ws/tablet?token=viewer")
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .reply(&connect)
+            .await;
+        assert_eq!(reply.status(), warp::http::StatusCode::FORBIDDEN);
+        assert!(!srv.sockets.read().await["tablet"].connecting);
+
+        assert!(warp::test::ws()
+            .path("/ws/tablet?token=operator")
+            .handshake(connect)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_hello_negotiates_features() {
+        let srv = mock_srv(MockDB::new());
+        let (tx, mut updates) = tokio::sync::mpsc::unbounded_channel();
+        let client = WSocket {
+            sender: Some(tx),
+            ..Default::default()
+        };
+        srv.sockets.write().await.insert("tablet".into(), client);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let hello = r#"{"hello": {"version": 2, "features": ["removals", "teleport"]}}"#;
+        handle_ws_message("tablet", warp::ws::Message::text(hello), &srv, tx.clone()).await;
+        let reply = WSMessage::try_from(rx.recv().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(
+            reply,
+            WSMessage::Hello { version, features }
+                if version == PROTOCOL_VERSION && features == vec!["removals".to_string()]
+        ));
+
+        // health updates are not understood by the client
+        srv.broadcast(WSUpdate::Health {
+            database: ConnectionState::Disconnected,
+        })
+        .await;
+        let removed = WSUpdate::Removed {
+            table: "bow".into(),
+            ids: vec![1],
+        };
+        srv.broadcast(removed.clone()).await;
+        assert_eq!(
+            updates.recv().await.unwrap().unwrap(),
+            warp::ws::Message::text(serde_json::to_string(&removed).unwrap())
         );
+
+        handle_ws_message("tablet", warp::ws::Message::text(hello), &srv, tx).await;
+        let reply = WSMessage::try_from(rx.recv().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(
+            reply,
+            WSMessage::Error {
+                code: ErrorCode::BadRequest,
+                ..
+            }
+        ));
+
+        // features of newer versions are not offered to older clients
+        let protocol = Protocol::negotiate(1, &["removals".to_string()]).unwrap();
+        assert!(!protocol.supports(Feature::Removals));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_clients_get_no_features() {
+        let srv = mock_srv(MockDB::new());
+        let (tx, mut updates) = tokio::sync::mpsc::unbounded_channel();
+        let client = WSocket {
+            sender: Some(tx),
+            ..Default::default()
+        };
+        srv.sockets.write().await.insert("tablet".into(), client);
+
+        // errors are sent as version 1 error updates
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let calibrate = WSMessage::Request {
+            id: None,
+            request: WSRequest::Command(MachineCommand::Calibrate),
+        };
+        handle_ws_message("tablet", calibrate.into(), &srv, tx).await;
+        let reply = WSMessage::try_from(rx.recv().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(
+            reply,
+            WSMessage::Response {
+                update: WSUpdate::Error(_),
+                ..
+            }
+        ));
+        assert_eq!(
+            srv.sockets.read().await["tablet"].protocol,
+            Some(Protocol {
+                version: LEGACY_PROTOCOL_VERSION,
+                features: HashSet::new(),
+            })
+        );
+
+        let bow = Bow {
+            id: 1,
+            name: "bow".into(),
+            max_draw_distance: 0.9,
+            remainder_arrow_length: 0.1,
+        };
+        let point = MeasurePoint {
+            id: 1,
+            measure_id: 1,
+            time: 0,
+            draw_distance: 0.1,
+            force: 1.0,
+        };
+        let all = vec![
+            WSUpdate::Alive {},
+            WSUpdate::Bow(bow.clone()),
+            WSUpdate::BowList(vec![bow]),
+            WSUpdate::MeasureSeriesList(Vec::new()),
+            WSUpdate::ArrowList(Vec::new()),
+            WSUpdate::MeasureList(Vec::new()),
+            WSUpdate::MeasurePointList(vec![point.clone()]),
+            WSUpdate::LiveMeasurePoints {
+                measure_id: 1,
+                points: vec![point],
+            },
+            WSUpdate::MeasureResultList(Vec::new()),
+            WSUpdate::Status(MachineStatus::Pause),
+            WSUpdate::Health {
+                database: ConnectionState::Disconnected,
+            },
+            WSUpdate::Resync {},
+            WSUpdate::Subscriptions(vec![Topic::Status]),
+            WSUpdate::Removed {
+                table: "bow".into(),
+                ids: vec![1],
+            },
+            WSUpdate::Error("error".into()),
+        ];
+        for update in all {
+            srv.broadcast(update).await;
+        }
+        let mut received = Vec::new();
+        while let Ok(msg) = updates.try_recv() {
+            let update: serde_json::Value =
+                serde_json::from_str(msg.unwrap().to_str().unwrap()).unwrap();
+            received.extend(update.as_object().unwrap().keys().cloned());
+        }
+        let version_1 = [
+            "alive",
+            "bowlist",
+            "measureserieslist",
+            "arrowlist",
+            "measurelist",
+            "measurepointlist",
+            "status",
+            "error",
+        ];
+        assert_eq!(received, version_1);
+    }
+
+    #[tokio::test]
+    async fn test_incompatible_clients_are_rejected() {
+        let srv = mock_srv(MockDB::new());
+        srv.sockets
+            .write()
+            .await
+            .insert("tablet".into(), Default::default());
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let hello = r#"{"hello": {"version": 0}}"#;
+        handle_ws_message("tablet", warp::ws::Message::text(hello), &srv, tx).await;
+        let reply = WSMessage::try_from(rx.recv().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(
+            reply,
+            WSMessage::Error {
+                code: ErrorCode::Incompatible,
+                ..
+            }
+        ));
+        assert!(rx.recv().await.unwrap().unwrap().is_close());
+        assert!(srv.sockets.read().await["tablet"].protocol.is_none());
+
+        // skipping the hello after reconnecting does not help
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let request = WSMessage::Request {
+            id: None,
+            request: WSRequest::Subscribe(vec![Topic::Status]),
+        };
+        handle_ws_message("tablet", request.into(), &srv, tx).await;
+        let reply = WSMessage::try_from(rx.recv().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(
+            reply,
+            WSMessage::Response {
+                update: WSUpdate::Error(_),
+                ..
+            }
+        ));
+        assert!(rx.recv().await.unwrap().unwrap().is_close());
+        assert!(srv.sockets.read().await["tablet"].topics.is_none());
     }
 }
@@ -0,0 +1,296 @@
+use super::super::message::*;
+use super::super::models::*;
+use super::auth;
+use super::database::ArrowDB;
+use super::handler::{self, WSError};
+use super::Webserver;
+
+use serde::Deserialize;
+use std::convert::Infallible;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::reply::{json, Response};
+use warp::{Filter, Reply};
+
+/// Body of a series rename.
+#[derive(Deserialize, Debug)]
+struct Rename {
+    name: String,
+}
+
+/// REST endpoints below `/api`. Each maps to the websocket request of the
+/// same meaning and replies with the same JSON, errors are sent as
+/// `{"error": {"code": ..., "message": ...}}` with a matching status code.
+/// The session token is given as for the websocket, see `auth::token`.
+pub fn routes<F: ArrowDB + 'static>(
+    srv: Webserver<F>,
+) -> impl Filter<Extract = (Response,), Error = warp::Rejection> + Clone {
+    requests()
+        .and(auth::with_role(srv.clone()))
+        .and(warp::any().map(move || srv.clone()))
+        .and_then(respond)
+}
+
+fn requests() -> BoxedFilter<(WSRequest,)> {
+    use warp::body::json;
+    use warp::{delete, get, post, put};
+
+    let bows = get()
+        .and(warp::path!("api" / "bows"))
+        .map(|| WSRequest::ListBows {})
+        .or(post()
+            .and(warp::path!("api" / "bows"))
+            .and(json())
+            .map(WSRequest::AddBow))
+        .unify()
+        .or(get()
+            .and(warp::path!("api" / "bows" / i32))
+            .map(|id| WSRequest::GetBow { id }))
+        .unify()
+        .or(put()
+            .and(warp::path!("api" / "bows" / i32))
+            .and(json())
+            .map(|id, bow: Bow| WSRequest::UpdateBow(Bow { id, ..bow })))
+        .unify()
+        .or(delete()
+            .and(warp::path!("api" / "bows" / i32))
+            .map(|id| WSRequest::DeleteBow { id }))
+        .unify()
+        .or(get()
+            .and(warp::path!("api" / "bows" / i32 / "series"))
+            .map(|bow_id| WSRequest::ListMeasureSeries { bow_id }))
+        .unify()
+        .or(get()
+            .and(warp::path!("api" / "bows" / i32 / "arrows"))
+            .map(|bow_id| WSRequest::ListArrows { bow_id }))
+        .unify();
+
+    let arrows = post()
+        .and(warp::path!("api" / "arrows"))
+        .and(json())
+        .map(WSRequest::AddArrow)
+        .or(put()
+            .and(warp::path!("api" / "arrows" / i32))
+            .and(json())
+            .map(|id, arrow: Arrow| WSRequest::UpdateArrow(Arrow { id, ..arrow })))
+        .unify()
+        .or(delete()
+            .and(warp::path!("api" / "arrows" / i32))
+            .map(|id| WSRequest::DeleteArrow { id }))
+        .unify();
+
+    let series = post()
+        .and(warp::path!("api" / "series"))
+        .and(json())
+        .map(WSRequest::NewMeasureSeries)
+        .or(put()
+            .and(warp::path!("api" / "series" / i32))
+            .and(json())
+            .map(|id, rename: Rename| WSRequest::RenameMeasureSeries {
+                id,
+                name: rename.name,
+            }))
+        .unify()
+        .or(delete()
+            .and(warp::path!("api" / "series" / i32))
+            .map(|id| WSRequest::DeleteMeasureSeries { id }))
+        .unify()
+        .or(get()
+            .and(warp::path!("api" / "series" / i32 / "measures"))
+            .map(|series_id| WSRequest::ListMeasures { series_id }))
+        .unify()
+        .or(get()
+            .and(warp::path!("api" / "series" / i32 / "results"))
+            .map(|series_id| WSRequest::ListMeasureResults { series_id }))
+        .unify();
+
+    let measures = post()
+        .and(warp::path!("api" / "measures"))
+        .and(json())
+        .map(WSRequest::StartMeasure)
+        .or(delete()
+            .and(warp::path!("api" / "measures" / i32))
+            .map(|id| WSRequest::DeleteMeasure { id }))
+        .unify()
+        .or(get()
+            .and(warp::path!("api" / "measures" / i32 / "points"))
+            .map(|measure_id| WSRequest::ListMeasurePoints { measure_id }))
+        .unify();
+
+    let machine = post()
+        .and(warp::path!("api" / "command"))
+        .and(json())
+        .map(WSRequest::Command)
+        .or(get()
+            .and(warp::path!("api" / "health"))
+            .map(|| WSRequest::GetHealth {}))
+        .unify();
+
+    bows.or(arrows)
+        .unify()
+        .or(series)
+        .unify()
+        .or(measures)
+        .unify()
+        .or(machine)
+        .unify()
+        .boxed()
+}
+
+async fn respond<F: ArrowDB>(
+    request: WSRequest,
+    role: Option<Role>,
+    srv: Webserver<F>,
+) -> Result<Response, Infallible> {
+    Ok(match handler::execute(&srv, None, role, request).await {
+        Ok(update) => json(&update).into_response(),
+        Err(e) => error_reply(e),
+    })
+}
+
+/// Error sent with the status code matching its category.
+pub fn error_reply(e: WSError) -> Response {
+    let code = e.code();
+    let error = WSMessage::Error {
+        id: None,
+        code,
+        message: e.to_string(),
+    };
+    warp::reply::with_status(json(&error), status(code)).into_response()
+}
+
+fn status(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::BadMessage | ErrorCode::BadRequest | ErrorCode::Incompatible => {
+            StatusCode::BAD_REQUEST
+        }
+        ErrorCode::NotFound => StatusCode::NOT_FOUND,
+        ErrorCode::Busy | ErrorCode::Fault => StatusCode::CONFLICT,
+        ErrorCode::Database | ErrorCode::Hardware => StatusCode::INTERNAL_SERVER_ERROR,
+        ErrorCode::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+        ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::database::traits::MockDB;
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    fn mock_srv(db: MockDB) -> Webserver<MockDB> {
+        let (tx, _rx) = tokio::sync::mpsc::channel(8);
+        let (control_tx, _control_rx) = control_channel(8);
+        Webserver {
+            shutdown_tx: tx,
+            control_tx,
+            sockets: Arc::new(RwLock::new(HashMap::new())),
+            db,
+            database_state: Arc::new(RwLock::new(ConnectionState::Connected)),
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            authentication: false,
+            frontend: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rest_requests() {
+        // the routes work on clones of the server
+        fn mock_db() -> MockDB {
+            let mut db = MockDB::new();
+            db.expect_list_measurement_series()
+                .withf(|id| *id == 3)
+                .returning(|_| Ok(Vec::new()));
+            db.expect_get_bow().returning(|id| match id {
+                3 => Ok(Bow {
+                    id,
+                    name: "bow".into(),
+                    max_draw_distance: 0.8,
+                    remainder_arrow_length: 0.1,
+                }),
+                _ => Err(sqlx::Error::RowNotFound),
+            });
+            db.expect_clone().returning(mock_db);
+            db
+        }
+        let routes = routes(mock_srv(mock_db()));
+
+        let reply = warp::test::request()
+            .path("/api/bows/3/series")
+            .reply(&routes)
+            .await;
+        assert_eq!(reply.status(), StatusCode::OK);
+        assert_eq!(reply.body(), r#"{"measureserieslist":[]}"#);
+
+        let reply = warp::test::request()
+            .path("/api/bows/3")
+            .reply(&routes)
+            .await;
+        assert_eq!(
+            reply.body(),
+            r#"{"bow":{"id":3,"name":"bow","max_draw_distance":0.8,"remainder_arrow_length":0.1}}"#
+        );
+
+        let reply = warp::test::request()
+            .path("/api/bows/4")
+            .reply(&routes)
+            .await;
+        assert_eq!(reply.status(), StatusCode::NOT_FOUND);
+        let error: WSMessage = serde_json::from_slice(reply.body()).unwrap();
+        assert!(matches!(
+            error,
+            WSMessage::Error {
+                code: ErrorCode::NotFound,
+                ..
+            }
+        ));
+
+        let reply = warp::test::request()
+            .method("POST")
+            .path("/api/bows")
+            .body("{}")
+            .reply(&routes)
+            .await;
+        assert_eq!(reply.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_viewers_cannot_control() {
+        fn mock_db() -> MockDB {
+            let mut db = MockDB::new();
+            db.expect_list_bows().returning(|| Ok(Vec::new()));
+            db.expect_clone().returning(mock_db);
+            db
+        }
+        let mut srv = mock_srv(mock_db());
+        srv.authentication = true;
+        let session = auth::Session {
+            user: "student".into(),
+            role: Role::Viewer,
+            expires: tokio::time::Instant::now() + auth::SESSION_TIMEOUT,
+        };
+        srv.sessions.write().await.insert("abc".into(), session);
+        let routes = routes(srv);
+
+        let reply = warp::test::request().path("/api/bows").reply(&routes).await;
+        assert_eq!(reply.status(), StatusCode::UNAUTHORIZED);
+
+        let reply = warp::test::request()
+            .path("/api/bows?token=abc")
+            .reply(&routes)
+            .await;
+        assert_eq!(reply.status(), StatusCode::OK);
+
+        let reply = warp::test::request()
+            .method("POST")
+            .path("/api/command")
+            .header("authorization", "Bearer abc")
+            .body(r#""shutdown""#)
+            .reply(&routes)
+            .await;
+        assert_eq!(reply.status(), StatusCode::FORBIDDEN);
+    }
+}
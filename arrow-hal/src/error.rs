@@ -0,0 +1,19 @@
+#[derive(Debug, thiserror::Error)]
+pub enum HalError {
+    #[error("motor error: {0}")]
+    Motor(String),
+
+    #[error("force sensor error: {0}")]
+    ForceSensor(String),
+
+    #[error("encoder error: {0}")]
+    Encoder(String),
+
+    #[error("light gate error: {0}")]
+    LightGate(String),
+
+    #[error("safety limit exceeded: {0}")]
+    LimitExceeded(String),
+}
+
+pub type Result<T> = std::result::Result<T, HalError>;
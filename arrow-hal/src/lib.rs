@@ -1,20 +1,254 @@
+pub mod error;
 mod message;
-use std::error::Error;
+pub mod safety;
+pub mod simulation;
+pub mod traits;
+
+pub use error::{HalError, Result};
+pub use safety::SafetyLimits;
+pub use simulation::{SimulatedBow, SimulatedHardware};
+pub use traits::{Encoder, ForceSensor, LightGates, Motor};
+
+/// Machine constants needed to convert raw sensor readings.
+#[derive(Debug, Clone)]
+pub struct HardwareConfig {
+    /// Carriage travel per encoder rotation in meters.
+    pub distance_per_rotation: f64,
+    /// Distance between the two light gates in meters.
+    pub speed_measure_distance: f64,
+    /// Limits of the machine itself, never exceeded regardless of the mounted bow.
+    pub limits: SafetyLimits,
+}
+
+/// A single reading of the draw sensors.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    /// Draw distance in meters from the home position.
+    pub draw_distance: f64,
+    /// Draw force in newton.
+    pub force: f64,
+}
+
+pub struct Hardware {
+    config: HardwareConfig,
+    limits: SafetyLimits,
+    motor: Box<dyn Motor>,
+    force_sensor: Box<dyn ForceSensor>,
+    encoder: Box<dyn Encoder>,
+    light_gates: Box<dyn LightGates>,
+}
+
+impl Hardware {
+    pub fn new(
+        config: HardwareConfig,
+        motor: Box<dyn Motor>,
+        force_sensor: Box<dyn ForceSensor>,
+        encoder: Box<dyn Encoder>,
+        light_gates: Box<dyn LightGates>,
+    ) -> Self {
+        Hardware {
+            limits: config.limits,
+            config,
+            motor,
+            force_sensor,
+            encoder,
+            light_gates,
+        }
+    }
+
+    /// Brings the carriage to its home position and zeroes the encoder there.
+    pub fn start(&mut self) -> Result<()> {
+        self.motor.home()?;
+        self.encoder.reset()
+    }
+
+    /// Homes the carriage and zeroes both encoder and load cell.
+    pub fn calibrate(&mut self) -> Result<()> {
+        self.start()?;
+        self.force_sensor.tare()
+    }
+
+    pub fn config(&self) -> &HardwareConfig {
+        &self.config
+    }
+
+    pub fn limits(&self) -> SafetyLimits {
+        self.limits
+    }
+
+    /// Restricts the limits further, e.g. for the mounted bow.
+    /// The machine limits from the config always stay in place.
+    pub fn set_limits(&mut self, limits: SafetyLimits) {
+        self.limits = self.config.limits.min(limits);
+    }
+
+    pub fn reset_limits(&mut self) {
+        self.limits = self.config.limits;
+    }
+
+    pub fn draw_distance(&mut self) -> Result<f64> {
+        Ok(self.encoder.rotations()? * self.config.distance_per_rotation)
+    }
+
+    pub fn force(&mut self) -> Result<f64> {
+        self.force_sensor.force()
+    }
+
+    /// Reads the draw sensors and stops the motor if a safety limit is exceeded.
+    pub fn sample(&mut self) -> Result<Sample> {
+        let sample = Sample {
+            draw_distance: self.draw_distance()?,
+            force: self.force()?,
+        };
+        if let Err(e) = self.limits.check(&sample) {
+            let _ = self.motor.stop();
+            return Err(e);
+        }
+        Ok(sample)
+    }
+
+    /// Moves the carriage, refusing to draw beyond the draw distance limit.
+    pub fn draw(&mut self, distance: f64) -> Result<()> {
+        if distance > 0.0 {
+            let target = self.draw_distance()? + distance;
+            if let Err(e) = self.limits.check_draw_distance(target) {
+                let _ = self.motor.stop();
+                return Err(e);
+            }
+        }
+        self.motor.move_by(distance)
+    }
+
+    pub fn stop(&mut self) -> Result<()> {
+        self.motor.stop()
+    }
+
+    pub fn release(&mut self) -> Result<()> {
+        self.motor.release()
+    }
+
+    pub fn home(&mut self) -> Result<()> {
+        self.motor.home()
+    }
+
+    pub fn arm_light_gates(&mut self) -> Result<()> {
+        self.light_gates.arm()
+    }
+
+    /// Arrow speed in meters per second, `None` until the arrow passed both light gates.
+    pub fn arrow_speed(&mut self) -> Result<Option<f64>> {
+        Ok(self
+            .light_gates
+            .transit_time()?
+            .map(|t| self.config.speed_measure_distance / t.as_secs_f64()))
+    }
+}
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    struct FakeMotor;
+    struct FakeForceSensor(f64);
+    struct FakeEncoder(f64);
+    struct FakeLightGates(Option<Duration>);
+
+    impl Motor for FakeMotor {
+        fn move_by(&mut self, _distance: f64) -> Result<()> {
+            Ok(())
+        }
+        fn stop(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn release(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn home(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ForceSensor for FakeForceSensor {
+        fn force(&mut self) -> Result<f64> {
+            Ok(self.0)
+        }
+        fn tare(&mut self) -> Result<()> {
+            self.0 = 0.0;
+            Ok(())
+        }
+    }
+
+    impl Encoder for FakeEncoder {
+        fn rotations(&mut self) -> Result<f64> {
+            Ok(self.0)
+        }
+        fn reset(&mut self) -> Result<()> {
+            self.0 = 0.0;
+            Ok(())
+        }
+    }
+
+    impl LightGates for FakeLightGates {
+        fn arm(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn transit_time(&mut self) -> Result<Option<Duration>> {
+            Ok(self.0)
+        }
+    }
+
+    fn hardware(rotations: f64, transit: Option<Duration>) -> Hardware {
+        Hardware::new(
+            HardwareConfig {
+                distance_per_rotation: 30e-3,
+                speed_measure_distance: 20e-2,
+                limits: SafetyLimits {
+                    max_draw_distance: 0.85,
+                    max_draw_force: 30.0,
+                },
+            },
+            Box::new(FakeMotor),
+            Box::new(FakeForceSensor(12.5)),
+            Box::new(FakeEncoder(rotations)),
+            Box::new(FakeLightGates(transit)),
+        )
+    }
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
-}
 
-pub struct Hardware {}
+    #[test]
+    fn sample_converts_rotations() {
+        let mut hw = hardware(10.0, None);
+        let sample = hw.sample().unwrap();
+        assert!((sample.draw_distance - 0.3).abs() < 1e-9);
+        assert_eq!(sample.force, 12.5);
+    }
 
-impl Hardware {
-    pub fn new() -> Result<Self, Box<dyn Error>> {
-        Ok(Hardware {})
+    #[test]
+    fn limits_stop_drawing() {
+        let mut hw = hardware(25.0, None);
+        assert!(matches!(hw.draw(0.2), Err(HalError::LimitExceeded(_))));
+        hw.draw(0.05).unwrap();
+
+        hw.set_limits(SafetyLimits {
+            max_draw_distance: 2.0,
+            max_draw_force: 10.0,
+        });
+        assert_eq!(hw.limits().max_draw_distance, 0.85);
+        assert!(matches!(hw.sample(), Err(HalError::LimitExceeded(_))));
+        hw.reset_limits();
+        assert!(hw.sample().is_ok());
     }
 
-    pub fn start(&mut self) {}
+    #[test]
+    fn arrow_speed_from_transit_time() {
+        let mut hw = hardware(0.0, Some(Duration::from_millis(4)));
+        let speed = hw.arrow_speed().unwrap().unwrap();
+        assert!((speed - 50.0).abs() < 1e-9);
+        assert!(hardware(0.0, None).arrow_speed().unwrap().is_none());
+    }
 }
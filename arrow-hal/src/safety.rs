@@ -0,0 +1,44 @@
+use crate::error::{HalError, Result};
+use crate::Sample;
+use serde::{Deserialize, Serialize};
+
+/// Maximum draw distance and force the machine may reach.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafetyLimits {
+    /// Maximum draw distance in meters from the home position.
+    pub max_draw_distance: f64,
+    /// Maximum draw force in newton.
+    pub max_draw_force: f64,
+}
+
+impl SafetyLimits {
+    /// The tighter of both limits.
+    pub fn min(self, other: SafetyLimits) -> Self {
+        Self {
+            max_draw_distance: self.max_draw_distance.min(other.max_draw_distance),
+            max_draw_force: self.max_draw_force.min(other.max_draw_force),
+        }
+    }
+
+    pub fn check(&self, sample: &Sample) -> Result<()> {
+        self.check_draw_distance(sample.draw_distance)?;
+        if sample.force > self.max_draw_force {
+            return Err(HalError::LimitExceeded(format!(
+                "draw force {:.2} N exceeds limit of {:.2} N",
+                sample.force, self.max_draw_force
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn check_draw_distance(&self, draw_distance: f64) -> Result<()> {
+        if draw_distance > self.max_draw_distance {
+            return Err(HalError::LimitExceeded(format!(
+                "draw distance {:.3} m exceeds limit of {:.3} m",
+                draw_distance, self.max_draw_distance
+            )));
+        }
+        Ok(())
+    }
+}
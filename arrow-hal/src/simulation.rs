@@ -0,0 +1,288 @@
+use crate::error::{HalError, Result};
+use crate::traits::{Encoder, ForceSensor, LightGates, Motor};
+use crate::{Hardware, HardwareConfig};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Physical model of the simulated bow and arrow.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct SimulatedBow {
+    /// Carriage position in meters where it picks up the string.
+    pub rest_position: f64,
+    /// Draw force in newton at `draw_length`.
+    pub draw_weight: f64,
+    /// Draw length in meters measured from `rest_position`.
+    pub draw_length: f64,
+    /// Exponent of the force-draw curve, 1.0 is a linear bow.
+    pub curve_exponent: f64,
+    /// Fraction of the stored energy transferred to the arrow.
+    pub efficiency: f64,
+    /// Arrow mass in kilogram.
+    pub arrow_mass: f64,
+}
+
+impl Default for SimulatedBow {
+    fn default() -> Self {
+        Self {
+            rest_position: 0.2,
+            draw_weight: 25.0,
+            draw_length: 0.55,
+            curve_exponent: 0.9,
+            efficiency: 0.75,
+            arrow_mass: 0.025,
+        }
+    }
+}
+
+impl SimulatedBow {
+    /// Force in newton with the string drawn by `draw` meters from its rest position.
+    pub fn force(&self, draw: f64) -> f64 {
+        if draw <= 0.0 {
+            return 0.0;
+        }
+        self.draw_weight * (draw / self.draw_length).powf(self.curve_exponent)
+    }
+
+    /// Energy in joule stored in the bow with the string drawn by `draw` meters.
+    pub fn stored_energy(&self, draw: f64) -> f64 {
+        if draw <= 0.0 {
+            return 0.0;
+        }
+        let p = self.curve_exponent + 1.0;
+        self.draw_weight * self.draw_length / p * (draw / self.draw_length).powf(p)
+    }
+
+    /// Speed of the arrow in meters per second when released at `draw` meters.
+    pub fn arrow_speed(&self, draw: f64) -> f64 {
+        (2.0 * self.efficiency * self.stored_energy(draw) / self.arrow_mass).sqrt()
+    }
+}
+
+#[derive(Debug)]
+struct Shot {
+    /// Tokio's clock, so the arrow follows the paused time of tests.
+    released_at: Instant,
+    transit: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct State {
+    bow: SimulatedBow,
+    config: HardwareConfig,
+    position: f64,
+    holds_string: bool,
+    encoder_offset: f64,
+    tare_offset: f64,
+    gates_armed: bool,
+    shot: Option<Shot>,
+}
+
+impl State {
+    fn raw_force(&self) -> f64 {
+        if self.holds_string {
+            self.bow.force(self.position - self.bow.rest_position)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Bow and machine simulated in software, usable wherever a [`Hardware`] is expected.
+#[derive(Clone)]
+pub struct SimulatedHardware {
+    state: Arc<Mutex<State>>,
+}
+
+impl SimulatedHardware {
+    pub fn new(config: HardwareConfig, bow: SimulatedBow) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                bow,
+                config,
+                position: 0.0,
+                holds_string: true,
+                encoder_offset: 0.0,
+                tare_offset: 0.0,
+                gates_armed: false,
+                shot: None,
+            })),
+        }
+    }
+
+    pub fn into_hardware(self) -> Hardware {
+        let config = self.lock().config.clone();
+        Hardware::new(
+            config,
+            Box::new(self.clone()),
+            Box::new(SimulatedForceSensor(self.clone())),
+            Box::new(SimulatedEncoder(self.clone())),
+            Box::new(SimulatedLightGates(self)),
+        )
+    }
+
+    /// Actual carriage position in meters, independent of the encoder zero point.
+    pub fn position(&self) -> f64 {
+        self.lock().position
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // a panic while holding the lock cannot leave the plain data in an invalid state
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Motor for SimulatedHardware {
+    fn move_by(&mut self, distance: f64) -> Result<()> {
+        let mut state = self.lock();
+        if state.position + distance < 0.0 {
+            return Err(HalError::Motor(
+                "carriage cannot move behind home position".into(),
+            ));
+        }
+        state.position += distance;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn release(&mut self) -> Result<()> {
+        let mut state = self.lock();
+        if !state.holds_string {
+            return Err(HalError::Motor("string is already released".into()));
+        }
+        let draw = state.position - state.bow.rest_position;
+        let speed = state.bow.arrow_speed(draw);
+        let transit = if speed > 0.0 {
+            Some(Duration::from_secs_f64(
+                state.config.speed_measure_distance / speed,
+            ))
+        } else {
+            None
+        };
+        state.holds_string = false;
+        state.shot = Some(Shot {
+            released_at: Instant::now(),
+            transit,
+        });
+        Ok(())
+    }
+
+    fn home(&mut self) -> Result<()> {
+        let mut state = self.lock();
+        state.position = 0.0;
+        state.holds_string = true;
+        Ok(())
+    }
+}
+
+struct SimulatedForceSensor(SimulatedHardware);
+
+impl ForceSensor for SimulatedForceSensor {
+    fn force(&mut self) -> Result<f64> {
+        let state = self.0.lock();
+        Ok(state.raw_force() - state.tare_offset)
+    }
+
+    fn tare(&mut self) -> Result<()> {
+        let mut state = self.0.lock();
+        state.tare_offset = state.raw_force();
+        Ok(())
+    }
+}
+
+struct SimulatedEncoder(SimulatedHardware);
+
+impl Encoder for SimulatedEncoder {
+    fn rotations(&mut self) -> Result<f64> {
+        let state = self.0.lock();
+        Ok((state.position - state.encoder_offset) / state.config.distance_per_rotation)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        let mut state = self.0.lock();
+        state.encoder_offset = state.position;
+        Ok(())
+    }
+}
+
+struct SimulatedLightGates(SimulatedHardware);
+
+impl LightGates for SimulatedLightGates {
+    fn arm(&mut self) -> Result<()> {
+        let mut state = self.0.lock();
+        state.gates_armed = true;
+        state.shot = None;
+        Ok(())
+    }
+
+    fn transit_time(&mut self) -> Result<Option<Duration>> {
+        let state = self.0.lock();
+        if !state.gates_armed {
+            return Err(HalError::LightGate("light gates are not armed".into()));
+        }
+        Ok(match &state.shot {
+            Some(Shot {
+                released_at,
+                transit: Some(transit),
+            }) if released_at.elapsed() >= *transit => Some(*transit),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SafetyLimits;
+
+    fn hardware() -> (SimulatedHardware, Hardware) {
+        let sim = SimulatedHardware::new(
+            HardwareConfig {
+                distance_per_rotation: 30e-3,
+                speed_measure_distance: 20e-2,
+                limits: SafetyLimits {
+                    max_draw_distance: 0.85,
+                    max_draw_force: 30.0,
+                },
+            },
+            SimulatedBow::default(),
+        );
+        (sim.clone(), sim.into_hardware())
+    }
+
+    #[test]
+    fn force_rises_after_rest_position() {
+        let (_, mut hw) = hardware();
+        hw.calibrate().unwrap();
+        hw.draw(0.1).unwrap();
+        assert_eq!(hw.force().unwrap(), 0.0);
+        hw.draw(0.4).unwrap();
+        let sample = hw.sample().unwrap();
+        assert!((sample.draw_distance - 0.5).abs() < 1e-9);
+        assert!(sample.force > 0.0);
+    }
+
+    #[test]
+    fn release_reaches_light_gates() {
+        let (sim, mut hw) = hardware();
+        hw.calibrate().unwrap();
+        hw.draw(0.6).unwrap();
+        hw.arm_light_gates().unwrap();
+        hw.release().unwrap();
+        assert_eq!(hw.force().unwrap(), 0.0);
+
+        let expected = SimulatedBow::default().arrow_speed(0.4);
+        std::thread::sleep(Duration::from_secs_f64(0.2 / expected));
+        let speed = hw.arrow_speed().unwrap().unwrap();
+        assert!((speed - expected).abs() < 1e-6);
+
+        hw.home().unwrap();
+        assert_eq!(sim.position(), 0.0);
+    }
+}
@@ -0,0 +1,45 @@
+use crate::error::Result;
+use std::time::Duration;
+
+/// Motor moving the draw carriage, including the release mechanism mounted on it.
+pub trait Motor: Send {
+    /// Moves the carriage by `distance` meters, positive values draw the string.
+    fn move_by(&mut self, distance: f64) -> Result<()>;
+
+    /// Stops any movement of the carriage immediately.
+    fn stop(&mut self) -> Result<()>;
+
+    /// Opens the release mechanism and lets go of the string.
+    fn release(&mut self) -> Result<()>;
+
+    /// Moves the carriage back to its home position and closes the release mechanism.
+    fn home(&mut self) -> Result<()>;
+}
+
+/// Load cell measuring the draw force.
+pub trait ForceSensor: Send {
+    /// Current force in newton.
+    fn force(&mut self) -> Result<f64>;
+
+    /// Sets the current load as zero point.
+    fn tare(&mut self) -> Result<()>;
+}
+
+/// Rotary encoder on the draw carriage drive.
+pub trait Encoder: Send {
+    /// Rotations since the last reset, fractions included.
+    fn rotations(&mut self) -> Result<f64>;
+
+    /// Sets the current position as zero point.
+    fn reset(&mut self) -> Result<()>;
+}
+
+/// The two light gates used for measuring the arrow speed.
+pub trait LightGates: Send {
+    /// Clears previous readings and waits for the next arrow.
+    fn arm(&mut self) -> Result<()>;
+
+    /// Time the arrow took from the first to the second gate.
+    /// Returns `None` until the arrow passed both gates.
+    fn transit_time(&mut self) -> Result<Option<Duration>>;
+}
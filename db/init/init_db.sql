@@ -0,0 +1,6 @@
+-- Runs on the first start of the database container, the schema is created by arrow-ctl
+-- with the migrations in arrow-ctl/migrations.
+CREATE ROLE arrow;
+ALTER ROLE arrow WITH LOGIN PASSWORD '14b8330c76fe611f9a62618f7dd37cd4';
+ALTER DATABASE arrow OWNER TO arrow;
+GRANT USAGE, CREATE ON SCHEMA public TO arrow;
@@ -0,0 +1,65 @@
+-- SQLite version of the migrations in arrow-ctl/migrations, change notifications are sent by the application.
+-- Timestamps are stored as microseconds since the unix epoch (UTC).
+
+CREATE TABLE IF NOT EXISTS bow (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	name TEXT UNIQUE NOT NULL,
+	max_draw_distance REAL NOT NULL,
+	remainder_arrow_length REAL NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS measure_series (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	name TEXT UNIQUE NOT NULL,
+	rest_position REAL NOT NULL,
+	draw_distance REAL,
+	draw_force REAL,
+	time INTEGER NOT NULL,
+	bow_id INTEGER NOT NULL REFERENCES bow(id) ON UPDATE CASCADE ON DELETE CASCADE,
+	CONSTRAINT chk_end_condition CHECK (draw_distance IS NOT NULL OR draw_force IS NOT NULL)
+);
+
+CREATE TABLE IF NOT EXISTS arrow (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	name TEXT,
+	head_weight REAL,
+	spline REAL,
+	feather_length REAL,
+	feather_type TEXT,
+	length REAL NOT NULL,
+	weight REAL NOT NULL,
+	bow_id INTEGER NOT NULL REFERENCES bow(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS measure (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	measure_interval REAL NOT NULL,
+	measure_series_id INTEGER NOT NULL REFERENCES measure_series(id) ON UPDATE CASCADE ON DELETE CASCADE,
+	arrow_id INTEGER NOT NULL REFERENCES arrow(id) ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS measure_point (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	time INTEGER NOT NULL,
+	draw_distance REAL NOT NULL,
+	force REAL NOT NULL,
+	measure_id INTEGER NOT NULL REFERENCES measure(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS measure_point_measure_id ON measure_point(measure_id);
+
+CREATE TABLE IF NOT EXISTS measure_result (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	measure_id INTEGER UNIQUE NOT NULL REFERENCES measure(id) ON UPDATE CASCADE ON DELETE CASCADE,
+	arrow_speed REAL NOT NULL,
+	potential_energy REAL NOT NULL,
+	kinetic_energy REAL NOT NULL,
+	efficiency REAL NOT NULL,
+	wasted_energy REAL NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS account (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	name TEXT UNIQUE NOT NULL,
+	password_hash TEXT NOT NULL,
+	role TEXT NOT NULL CHECK (role IN ('viewer', 'operator'))
+);
@@ -1,4 +1,5 @@
 #!/bin/sh
 set -e
 
-./arrow
+# there are no drivers for the machine hardware yet
+./arrow --simulate --static-dir www